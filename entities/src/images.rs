@@ -8,7 +8,7 @@ use sea_orm::entity::prelude::*;
 pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
-    pub user_id: i64,
+    pub user_id: Option<i64>,
     pub file_id: String,
     pub unique_id: String,
     pub creation_time: DateTime,
@@ -16,10 +16,40 @@ pub struct Model {
     pub embedding: Vec<f32>,
     pub media_type: MediaType,
     pub uses_count: i32,
+    pub embedding_model: String,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub caption: Option<String>,
+    #[sea_orm(column_type = "custom(\"vector\")", nullable)]
+    pub caption_embedding: Option<Vec<f32>>,
+    pub deleted_at: Option<DateTime>,
+    pub phash: Option<i64>,
+    pub collection_id: Option<i32>,
+    pub group_id: Option<i64>,
+    pub preview_file_id: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::collections::Entity",
+        from = "Column::CollectionId",
+        to = "super::collections::Column::Id",
+        on_update = "NoAction",
+        on_delete = "SetNull"
+    )]
+    Collections,
+    #[sea_orm(
+        belongs_to = "super::groups::Entity",
+        from = "Column::GroupId",
+        to = "super::groups::Column::Id",
+        on_update = "NoAction",
+        on_delete = "NoAction"
+    )]
+    Groups,
+    #[sea_orm(has_many = "super::reindex_failures::Entity")]
+    ReindexFailures,
+    #[sea_orm(has_many = "super::tags::Entity")]
+    Tags,
     #[sea_orm(
         belongs_to = "super::users::Entity",
         from = "Column::UserId",
@@ -30,6 +60,30 @@ pub enum Relation {
     Users,
 }
 
+impl Related<super::collections::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Collections.def()
+    }
+}
+
+impl Related<super::groups::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Groups.def()
+    }
+}
+
+impl Related<super::reindex_failures::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ReindexFailures.def()
+    }
+}
+
+impl Related<super::tags::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Tags.def()
+    }
+}
+
 impl Related<super::users::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Users.def()
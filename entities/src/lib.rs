@@ -2,6 +2,14 @@
 
 pub mod prelude;
 
+pub mod collection_subscriptions;
+pub mod collections;
+pub mod groups;
 pub mod images;
+pub mod query_embeddings;
+pub mod reindex_failures;
+pub mod reindex_jobs;
 pub mod sea_orm_active_enums;
+pub mod tags;
+pub mod translations;
 pub mod users;
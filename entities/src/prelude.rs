@@ -1,4 +1,12 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.10
 
+pub use super::collection_subscriptions::Entity as CollectionSubscriptions;
+pub use super::collections::Entity as Collections;
+pub use super::groups::Entity as Groups;
 pub use super::images::Entity as Images;
+pub use super::query_embeddings::Entity as QueryEmbeddings;
+pub use super::reindex_failures::Entity as ReindexFailures;
+pub use super::reindex_jobs::Entity as ReindexJobs;
+pub use super::tags::Entity as Tags;
+pub use super::translations::Entity as Translations;
 pub use super::users::Entity as Users;
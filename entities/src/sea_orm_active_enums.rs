@@ -5,6 +5,10 @@ use sea_orm::entity::prelude::*;
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "media_type")]
 pub enum MediaType {
+    #[sea_orm(string_value = "animation")]
+    Animation,
+    #[sea_orm(string_value = "document")]
+    Document,
     #[sea_orm(string_value = "photo")]
     Photo,
     #[sea_orm(string_value = "sticker")]
@@ -8,14 +8,45 @@ pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub id: i64,
     pub last_activity: DateTime,
+    pub group_id: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::collection_subscriptions::Entity")]
+    CollectionSubscriptions,
+    #[sea_orm(has_many = "super::collections::Entity")]
+    Collections,
+    #[sea_orm(
+        belongs_to = "super::groups::Entity",
+        from = "Column::GroupId",
+        to = "super::groups::Column::Id",
+        on_update = "NoAction",
+        on_delete = "SetNull"
+    )]
+    Groups,
     #[sea_orm(has_many = "super::images::Entity")]
     Images,
 }
 
+impl Related<super::collection_subscriptions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::CollectionSubscriptions.def()
+    }
+}
+
+impl Related<super::collections::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Collections.def()
+    }
+}
+
+impl Related<super::groups::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Groups.def()
+    }
+}
+
 impl Related<super::images::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Images.def()
@@ -5,6 +5,23 @@ mod m20240205_114643_create_images;
 mod m20240214_091109_add_image_type;
 mod m20240214_125213_image_uses_count;
 mod m20240326_130351_add_video_type;
+mod m20261017_093012_create_reindex_jobs;
+mod m20261017_104511_add_embedding_model;
+mod m20261017_112240_create_embedding_index;
+mod m20261017_121537_create_tags;
+mod m20261017_130904_add_image_caption;
+mod m20261017_140215_add_animation_type;
+mod m20261017_143348_add_document_type;
+mod m20261017_152736_add_image_deleted_at;
+mod m20261017_160120_create_translations;
+mod m20261017_165810_add_image_phash;
+mod m20261017_173226_create_collections;
+mod m20261017_181502_create_collection_subscriptions;
+mod m20261017_185937_create_groups;
+mod m20261017_193248_create_query_embeddings;
+mod m20261017_201455_add_image_preview_file_id;
+mod m20261017_204310_add_translation_provider;
+mod m20261017_210527_add_query_embedding_provider;
 
 pub struct Migrator;
 
@@ -17,6 +34,23 @@ impl MigratorTrait for Migrator {
             Box::new(m20240214_091109_add_image_type::Migration),
             Box::new(m20240214_125213_image_uses_count::Migration),
             Box::new(m20240326_130351_add_video_type::Migration),
+            Box::new(m20261017_093012_create_reindex_jobs::Migration),
+            Box::new(m20261017_104511_add_embedding_model::Migration),
+            Box::new(m20261017_112240_create_embedding_index::Migration),
+            Box::new(m20261017_121537_create_tags::Migration),
+            Box::new(m20261017_130904_add_image_caption::Migration),
+            Box::new(m20261017_140215_add_animation_type::Migration),
+            Box::new(m20261017_143348_add_document_type::Migration),
+            Box::new(m20261017_152736_add_image_deleted_at::Migration),
+            Box::new(m20261017_160120_create_translations::Migration),
+            Box::new(m20261017_165810_add_image_phash::Migration),
+            Box::new(m20261017_173226_create_collections::Migration),
+            Box::new(m20261017_181502_create_collection_subscriptions::Migration),
+            Box::new(m20261017_185937_create_groups::Migration),
+            Box::new(m20261017_193248_create_query_embeddings::Migration),
+            Box::new(m20261017_201455_add_image_preview_file_id::Migration),
+            Box::new(m20261017_204310_add_translation_provider::Migration),
+            Box::new(m20261017_210527_add_query_embedding_provider::Migration),
         ]
     }
 }
@@ -39,4 +39,5 @@ pub enum Users {
     Table,
     Id,
     LastActivity,
+    GroupId,
 }
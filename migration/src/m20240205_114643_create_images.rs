@@ -91,4 +91,12 @@ pub enum Images {
     UniqueId,
     CreationTime,
     Embedding,
+    EmbeddingModel,
+    Caption,
+    CaptionEmbedding,
+    DeletedAt,
+    Phash,
+    CollectionId,
+    GroupId,
+    PreviewFileId,
 }
@@ -0,0 +1,130 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240205_114643_create_images::Images;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ReindexJobs::Table)
+                    .col(
+                        ColumnDef::new(ReindexJobs::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ReindexJobs::ChatId).big_integer().not_null())
+                    .col(ColumnDef::new(ReindexJobs::MessageId).integer().not_null())
+                    .col(
+                        ColumnDef::new(ReindexJobs::LastImageId)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(ReindexJobs::Processed)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(ReindexJobs::Failed)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(ReindexJobs::CreationTime)
+                            .date_time()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(ColumnDef::new(ReindexJobs::FinishTime).date_time())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(ReindexFailures::Table)
+                    .col(
+                        ColumnDef::new(ReindexFailures::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ReindexFailures::JobId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(ReindexFailures::Table, ReindexFailures::JobId)
+                            .to(ReindexJobs::Table, ReindexJobs::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        ColumnDef::new(ReindexFailures::ImageId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(ReindexFailures::Table, ReindexFailures::ImageId)
+                            .to(Images::Table, Images::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(ReindexFailures::Error).text().not_null())
+                    .col(
+                        ColumnDef::new(ReindexFailures::CreationTime)
+                            .date_time()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ReindexFailures::Table).to_owned())
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(ReindexJobs::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum ReindexJobs {
+    Table,
+    Id,
+    ChatId,
+    MessageId,
+    LastImageId,
+    Processed,
+    Failed,
+    CreationTime,
+    FinishTime,
+}
+
+#[derive(DeriveIden)]
+pub enum ReindexFailures {
+    Table,
+    Id,
+    JobId,
+    ImageId,
+    Error,
+    CreationTime,
+}
@@ -0,0 +1,47 @@
+use sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+use crate::m20240205_114643_create_images::Images;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Every existing embedding was produced by the model from app.py.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Images::Table)
+                    .add_column(
+                        ColumnDef::new(Images::EmbeddingModel)
+                            .string()
+                            .not_null()
+                            .default("laion/CLIP-ViT-H-14-laion2B-s32B-b79K"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        let db = manager.get_connection();
+        db.execute(Statement::from_string(
+            DatabaseBackend::Postgres,
+            "ALTER TABLE images ALTER COLUMN embedding_model DROP DEFAULT;",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Images::Table)
+                    .drop_column(Images::EmbeddingModel)
+                    .to_owned(),
+            )
+            .await
+    }
+}
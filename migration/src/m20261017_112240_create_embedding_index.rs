@@ -0,0 +1,31 @@
+use sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute(Statement::from_string(
+            DatabaseBackend::Postgres,
+            "CREATE INDEX IF NOT EXISTS images_embedding_hnsw_idx \
+            ON images USING hnsw (embedding vector_cosine_ops);",
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute(Statement::from_string(
+            DatabaseBackend::Postgres,
+            "DROP INDEX IF EXISTS images_embedding_hnsw_idx;",
+        ))
+        .await?;
+
+        Ok(())
+    }
+}
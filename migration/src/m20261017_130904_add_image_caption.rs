@@ -0,0 +1,35 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m20240205_114643_create_images::Images;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Images::Table)
+                    .add_column(ColumnDef::new(Images::Caption).text())
+                    .add_column(
+                        ColumnDef::new(Images::CaptionEmbedding).custom(Alias::new("vector(1024)")),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Images::Table)
+                    .drop_column(Images::Caption)
+                    .drop_column(Images::CaptionEmbedding)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,96 @@
+use sea_orm_migration::prelude::*;
+
+use crate::{m20240205_113957_create_users::Users, m20240205_114643_create_images::Images};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Collections::Table)
+                    .col(
+                        ColumnDef::new(Collections::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Collections::UserId).big_integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(Collections::Table, Collections::UserId)
+                            .to(Users::Table, Users::Id),
+                    )
+                    .col(ColumnDef::new(Collections::Name).string().not_null())
+                    .col(
+                        ColumnDef::new(Collections::CreationTime)
+                            .date_time()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-collections-user_id-name")
+                    .table(Collections::Table)
+                    .col(Collections::UserId)
+                    .col(Collections::Name)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Images::Table)
+                    .add_column(ColumnDef::new(Images::CollectionId).integer())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk-images-collection_id")
+                            .from_tbl(Images::Table)
+                            .from_col(Images::CollectionId)
+                            .to_tbl(Collections::Table)
+                            .to_col(Collections::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Images::Table)
+                    .drop_column(Images::CollectionId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(Collections::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum Collections {
+    Table,
+    Id,
+    UserId,
+    Name,
+    CreationTime,
+    ShareToken,
+}
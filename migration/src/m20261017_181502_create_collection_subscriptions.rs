@@ -0,0 +1,123 @@
+use sea_orm_migration::prelude::*;
+
+use crate::{
+    m20240205_113957_create_users::Users, m20261017_173226_create_collections::Collections,
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Collections::Table)
+                    .add_column(ColumnDef::new(Collections::ShareToken).string())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-collections-share_token")
+                    .table(Collections::Table)
+                    .col(Collections::ShareToken)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(CollectionSubscriptions::Table)
+                    .col(
+                        ColumnDef::new(CollectionSubscriptions::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(CollectionSubscriptions::UserId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(
+                                CollectionSubscriptions::Table,
+                                CollectionSubscriptions::UserId,
+                            )
+                            .to(Users::Table, Users::Id),
+                    )
+                    .col(
+                        ColumnDef::new(CollectionSubscriptions::CollectionId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(
+                                CollectionSubscriptions::Table,
+                                CollectionSubscriptions::CollectionId,
+                            )
+                            .to(Collections::Table, Collections::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        ColumnDef::new(CollectionSubscriptions::CreationTime)
+                            .date_time()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-collection_subscriptions-user_id-collection_id")
+                    .table(CollectionSubscriptions::Table)
+                    .col(CollectionSubscriptions::UserId)
+                    .col(CollectionSubscriptions::CollectionId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(CollectionSubscriptions::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Collections::Table)
+                    .drop_column(Collections::ShareToken)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum CollectionSubscriptions {
+    Table,
+    Id,
+    UserId,
+    CollectionId,
+    CreationTime,
+}
@@ -0,0 +1,141 @@
+use sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+use crate::{m20240205_113957_create_users::Users, m20240205_114643_create_images::Images};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Groups::Table)
+                    .col(
+                        ColumnDef::new(Groups::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(Groups::CreationTime)
+                            .date_time()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Images belong either to a user or to a group.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Images::Table)
+                    .modify_column(ColumnDef::new(Images::UserId).big_integer().null())
+                    .add_column(ColumnDef::new(Images::GroupId).big_integer())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk-images-group_id")
+                            .from_tbl(Images::Table)
+                            .from_col(Images::GroupId)
+                            .to_tbl(Groups::Table)
+                            .to_col(Groups::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Group library the user searches with the `g:` prefix.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(Users::GroupId).big_integer())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk-users-group_id")
+                            .from_tbl(Users::Table)
+                            .from_col(Users::GroupId)
+                            .to_tbl(Groups::Table)
+                            .to_col(Groups::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Group chats used to be stored as users, their ids are negative.
+        let db = manager.get_connection();
+        for statement in [
+            "INSERT INTO groups (id) SELECT id FROM users WHERE id < 0;",
+            "UPDATE images SET group_id = user_id, user_id = NULL WHERE user_id < 0;",
+            "DELETE FROM collection_subscriptions WHERE user_id < 0;",
+            "DELETE FROM collections WHERE user_id < 0;",
+            "DELETE FROM users WHERE id < 0;",
+            "ALTER TABLE images ADD CONSTRAINT images_owner_check \
+                CHECK ((user_id IS NULL) <> (group_id IS NULL));",
+        ] {
+            db.execute(Statement::from_string(DatabaseBackend::Postgres, statement))
+                .await?;
+        }
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-images-group_id-unique_id")
+                    .table(Images::Table)
+                    .col(Images::GroupId)
+                    .col(Images::UniqueId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        for statement in [
+            "ALTER TABLE images DROP CONSTRAINT images_owner_check;",
+            "INSERT INTO users (id) SELECT id FROM groups ON CONFLICT DO NOTHING;",
+            "UPDATE images SET user_id = group_id WHERE group_id IS NOT NULL;",
+        ] {
+            db.execute(Statement::from_string(DatabaseBackend::Postgres, statement))
+                .await?;
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::GroupId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Images::Table)
+                    .drop_column(Images::GroupId)
+                    .modify_column(ColumnDef::new(Images::UserId).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(Groups::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum Groups {
+    Table,
+    Id,
+    CreationTime,
+}
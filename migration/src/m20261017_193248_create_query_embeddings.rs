@@ -0,0 +1,50 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(QueryEmbeddings::Table)
+                    .col(ColumnDef::new(QueryEmbeddings::Model).text().not_null())
+                    .col(ColumnDef::new(QueryEmbeddings::Query).text().not_null())
+                    .col(
+                        ColumnDef::new(QueryEmbeddings::Embedding)
+                            .array(ColumnType::Float)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(QueryEmbeddings::CreationTime)
+                            .date_time()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(QueryEmbeddings::Model)
+                            .col(QueryEmbeddings::Query),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(QueryEmbeddings::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum QueryEmbeddings {
+    Table,
+    Model,
+    Query,
+    Embedding,
+    CreationTime,
+}
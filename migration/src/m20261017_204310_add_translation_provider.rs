@@ -0,0 +1,45 @@
+use sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        // Existing translations were all made by Yandex from Russian.
+        for sql in [
+            "ALTER TABLE translations \
+            ADD COLUMN provider text NOT NULL DEFAULT 'yandex', \
+            ADD COLUMN source_language text NOT NULL DEFAULT 'ru';",
+            "ALTER TABLE translations \
+            ALTER COLUMN provider DROP DEFAULT, \
+            ALTER COLUMN source_language DROP DEFAULT;",
+            "ALTER TABLE translations \
+            DROP CONSTRAINT translations_pkey, \
+            ADD PRIMARY KEY (provider, source_language, source);",
+        ] {
+            db.execute(Statement::from_string(DatabaseBackend::Postgres, sql))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        for sql in [
+            "DELETE FROM translations WHERE provider <> 'yandex' OR source_language <> 'ru';",
+            "ALTER TABLE translations \
+            DROP CONSTRAINT translations_pkey, \
+            ADD PRIMARY KEY (source);",
+            "ALTER TABLE translations DROP COLUMN provider, DROP COLUMN source_language;",
+        ] {
+            db.execute(Statement::from_string(DatabaseBackend::Postgres, sql))
+                .await?;
+        }
+
+        Ok(())
+    }
+}
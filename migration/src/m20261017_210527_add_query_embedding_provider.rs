@@ -0,0 +1,45 @@
+use sea_orm::{DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        // It's a cache, embeddings of unknown translations aren't worth
+        // keeping.
+        for sql in [
+            "DELETE FROM query_embeddings;",
+            "ALTER TABLE query_embeddings ADD COLUMN provider text NOT NULL;",
+            "ALTER TABLE query_embeddings \
+            DROP CONSTRAINT query_embeddings_pkey, \
+            ADD PRIMARY KEY (model, provider, query);",
+            "CREATE INDEX query_embeddings_creation_time_idx \
+            ON query_embeddings (creation_time);",
+        ] {
+            db.execute(Statement::from_string(DatabaseBackend::Postgres, sql))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        for sql in [
+            "DROP INDEX query_embeddings_creation_time_idx;",
+            "DELETE FROM query_embeddings;",
+            "ALTER TABLE query_embeddings \
+            DROP CONSTRAINT query_embeddings_pkey, \
+            ADD PRIMARY KEY (model, query);",
+            "ALTER TABLE query_embeddings DROP COLUMN provider;",
+        ] {
+            db.execute(Statement::from_string(DatabaseBackend::Postgres, sql))
+                .await?;
+        }
+
+        Ok(())
+    }
+}
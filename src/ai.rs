@@ -0,0 +1,419 @@
+use std::{
+    env,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+use tracing::*;
+
+/// Dimension of the `images.embedding` column.
+const EMBEDDING_DIM: usize = 1024;
+
+#[derive(Deserialize, Debug)]
+pub struct EmbeddingsResponse {
+    pub embeddings: Vec<Vec<f32>>,
+}
+
+impl EmbeddingsResponse {
+    pub fn get_one(self) -> Result<Vec<f32>> {
+        self.embeddings
+            .into_iter()
+            .next()
+            .context("empty embeddings")
+    }
+}
+
+#[async_trait]
+pub trait EmbeddingBackend: Send + Sync {
+    /// Identifier of the model producing the embeddings, stored with every
+    /// image so vectors from different models are never compared.
+    fn model(&self) -> &str;
+    async fn images_embeddings(&self, images: Vec<Vec<u8>>) -> Result<EmbeddingsResponse>;
+    async fn text_embeddings(&self, texts: Vec<String>) -> Result<EmbeddingsResponse>;
+}
+
+/// Client for the FastAPI model server from `app.py`. Image requests are
+/// already serialized by the batcher, so text queries never wait for them.
+pub struct HttpBackend {
+    client: Client,
+    url: String,
+    model: String,
+}
+
+impl HttpBackend {
+    pub fn new(url: String, model: String) -> Self {
+        Self {
+            client: Client::new(),
+            url,
+            model,
+        }
+    }
+}
+
+#[async_trait]
+impl EmbeddingBackend for HttpBackend {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn images_embeddings(&self, images: Vec<Vec<u8>>) -> Result<EmbeddingsResponse> {
+        let mut form = reqwest::multipart::Form::new();
+
+        for image in images {
+            let file_part = reqwest::multipart::Part::bytes(image).file_name("image");
+            form = form.part("files", file_part);
+        }
+
+        let req = self
+            .client
+            .post(format!("{}/images", self.url))
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(req.json().await?)
+    }
+
+    async fn text_embeddings(&self, texts: Vec<String>) -> Result<EmbeddingsResponse> {
+        #[derive(Serialize, Debug)]
+        struct TextRequest {
+            texts: Vec<String>,
+        }
+
+        let res = self
+            .client
+            .post(format!("{}/texts", self.url))
+            .json(&TextRequest { texts })
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(res.json().await?)
+    }
+}
+
+/// In-process backend returning deterministic unit vectors derived from the
+/// input bytes. Equal inputs always get equal embeddings.
+pub struct MockBackend;
+
+impl MockBackend {
+    fn embed(data: &[u8]) -> Vec<f32> {
+        // FNV-1a seed, then xorshift64 to fill the vector.
+        let mut state = data.iter().fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+        });
+        if state == 0 {
+            state = 1;
+        }
+
+        let mut vector: Vec<f32> = (0..EMBEDDING_DIM)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+            })
+            .collect();
+
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        vector.iter_mut().for_each(|x| *x /= norm);
+        vector
+    }
+}
+
+#[async_trait]
+impl EmbeddingBackend for MockBackend {
+    fn model(&self) -> &str {
+        "mock"
+    }
+
+    async fn images_embeddings(&self, images: Vec<Vec<u8>>) -> Result<EmbeddingsResponse> {
+        Ok(EmbeddingsResponse {
+            embeddings: images.iter().map(|i| Self::embed(i)).collect(),
+        })
+    }
+
+    async fn text_embeddings(&self, texts: Vec<String>) -> Result<EmbeddingsResponse> {
+        Ok(EmbeddingsResponse {
+            embeddings: texts.iter().map(|t| Self::embed(t.as_bytes())).collect(),
+        })
+    }
+}
+
+struct PendingImage {
+    image: Vec<u8>,
+    reply: oneshot::Sender<Result<Vec<f32>>>,
+}
+
+/// Collects images submitted within `window` into a single backend request
+/// of at most `max_batch` images.
+async fn run_batcher(
+    backend: Arc<dyn EmbeddingBackend>,
+    mut rx: mpsc::UnboundedReceiver<PendingImage>,
+    max_batch: usize,
+    window: Duration,
+) {
+    while let Some(first) = rx.recv().await {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + window;
+        while batch.len() < max_batch {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(pending)) => batch.push(pending),
+                _ => break,
+            }
+        }
+
+        let (images, replies): (Vec<_>, Vec<_>) =
+            batch.into_iter().map(|p| (p.image, p.reply)).unzip();
+        debug!("embedding batch of {} images", images.len());
+
+        let embeddings = if images.len() == 1 {
+            backend.images_embeddings(images).await
+        } else {
+            // One broken image fails the whole request, so fall back to
+            // embedding them one by one.
+            match backend.images_embeddings(images.clone()).await {
+                Ok(res) => Ok(res),
+                Err(e) => {
+                    warn!("batch embedding failed, retrying one by one: {e:#}");
+                    for (image, reply) in images.into_iter().zip(replies) {
+                        let res = backend
+                            .images_embeddings(vec![image])
+                            .await
+                            .and_then(EmbeddingsResponse::get_one);
+                        reply.send(res).ok();
+                    }
+                    continue;
+                }
+            }
+        };
+
+        match embeddings {
+            Ok(res) if res.embeddings.len() == replies.len() => {
+                for (reply, embedding) in replies.into_iter().zip(res.embeddings) {
+                    reply.send(Ok(embedding)).ok();
+                }
+            }
+            Ok(res) => {
+                let (got, expected) = (res.embeddings.len(), replies.len());
+                for reply in replies {
+                    reply
+                        .send(Err(anyhow!(
+                            "backend returned {got} embeddings for {expected} images"
+                        )))
+                        .ok();
+                }
+            }
+            Err(e) => {
+                for reply in replies {
+                    reply.send(Err(anyhow!("{e:#}"))).ok();
+                }
+            }
+        }
+    }
+}
+
+/// Encoder used for text queries and captions.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TextEncoder {
+    /// Text tower of the image model, expects texts translated to English.
+    Clip,
+    /// Multilingual text tower aligned with the image model, takes texts in
+    /// any language as they are.
+    Multilingual,
+}
+
+pub struct Ai {
+    backend: Arc<dyn EmbeddingBackend>,
+    batcher: mpsc::UnboundedSender<PendingImage>,
+    batch_size: NonZeroUsize,
+    multilingual: Option<Arc<dyn EmbeddingBackend>>,
+    multilingual_enabled: AtomicBool,
+}
+
+impl Ai {
+    /// Spawns the image batching task, so must be called inside a Tokio runtime.
+    pub fn new(
+        backend: Arc<dyn EmbeddingBackend>,
+        batch_size: NonZeroUsize,
+        window: Duration,
+    ) -> Self {
+        let (batcher, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_batcher(backend.clone(), rx, batch_size.get(), window));
+        Self {
+            backend,
+            batcher,
+            batch_size,
+            multilingual: None,
+            multilingual_enabled: AtomicBool::new(false),
+        }
+    }
+
+    /// Adds a multilingual text encoder, used instead of the image model's
+    /// text tower while enabled.
+    pub fn with_multilingual(mut self, backend: Arc<dyn EmbeddingBackend>, enabled: bool) -> Self {
+        self.multilingual = Some(backend);
+        self.multilingual_enabled = AtomicBool::new(enabled);
+        self
+    }
+
+    /// Picks the backend from `EMBEDDING_BACKEND` (`http` or `mock`) and the
+    /// batching parameters from `EMBEDDING_BATCH_SIZE` and
+    /// `EMBEDDING_BATCH_WINDOW_MS`. `EMBEDDING_MODEL` must name the model
+    /// served at `EMBEDDING_URL`.
+    ///
+    /// If `MULTILINGUAL_TEXT_URL` points to a multilingual text encoder with
+    /// the same `/texts` API, `TEXT_ENCODER` (`clip` or `multilingual`)
+    /// selects the initial encoder, it can be switched at runtime. The
+    /// default `MULTILINGUAL_TEXT_MODEL` was trained against the frozen
+    /// ViT-H-14 image tower, so its vectors are comparable with the images.
+    pub fn from_env() -> Result<Self> {
+        let backend: Arc<dyn EmbeddingBackend> =
+            match env::var("EMBEDDING_BACKEND").as_deref().unwrap_or("http") {
+                "http" => Arc::new(HttpBackend::new(
+                    env::var("EMBEDDING_URL")
+                        .unwrap_or_else(|_| String::from("http://127.0.0.1:8526")),
+                    env::var("EMBEDDING_MODEL")
+                        .unwrap_or_else(|_| String::from("laion/CLIP-ViT-H-14-laion2B-s32B-b79K")),
+                )),
+                "mock" => Arc::new(MockBackend),
+                other => bail!("unknown EMBEDDING_BACKEND: {other}"),
+            };
+        let batch_size = match env::var("EMBEDDING_BATCH_SIZE") {
+            Ok(size) => size
+                .parse()
+                .context("EMBEDDING_BATCH_SIZE must be a positive number")?,
+            Err(_) => NonZeroUsize::new(16).unwrap(),
+        };
+        let window = match env::var("EMBEDDING_BATCH_WINDOW_MS") {
+            Ok(ms) => ms.parse().context("invalid EMBEDDING_BATCH_WINDOW_MS")?,
+            Err(_) => 50,
+        };
+        let ai = Self::new(backend, batch_size, Duration::from_millis(window));
+
+        let enabled = match env::var("TEXT_ENCODER").as_deref().unwrap_or("clip") {
+            "clip" => false,
+            "multilingual" => true,
+            other => bail!("unknown TEXT_ENCODER: {other}"),
+        };
+        match env::var("MULTILINGUAL_TEXT_URL") {
+            Ok(url) => Ok(ai.with_multilingual(
+                Arc::new(HttpBackend::new(
+                    url,
+                    env::var("MULTILINGUAL_TEXT_MODEL").unwrap_or_else(|_| {
+                        String::from(
+                            "laion/CLIP-ViT-H-14-frozen-xlm-roberta-large-laion5B-s13B-b90k",
+                        )
+                    }),
+                )),
+                enabled,
+            )),
+            Err(_) if enabled => {
+                bail!("TEXT_ENCODER is multilingual but MULTILINGUAL_TEXT_URL isn't set")
+            }
+            Err(_) => Ok(ai),
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        self.backend.model()
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size.get()
+    }
+
+    /// Queues the image for the next batch and waits for its embedding.
+    pub async fn image_embedding(&self, image: Vec<u8>) -> Result<Vec<f32>> {
+        let (reply, rx) = oneshot::channel();
+        self.batcher
+            .send(PendingImage { image, reply })
+            .map_err(|_| anyhow!("embedding batcher stopped"))?;
+        rx.await.context("embedding batcher dropped request")?
+    }
+
+    pub fn text_encoder(&self) -> TextEncoder {
+        if self.multilingual_enabled.load(Ordering::Relaxed) {
+            TextEncoder::Multilingual
+        } else {
+            TextEncoder::Clip
+        }
+    }
+
+    /// Switches the text encoder, fails if no multilingual encoder is
+    /// configured.
+    pub fn set_text_encoder(&self, encoder: TextEncoder) -> Result<()> {
+        if encoder == TextEncoder::Multilingual && self.multilingual.is_none() {
+            bail!("multilingual text encoder isn't configured");
+        }
+        self.multilingual_enabled
+            .store(encoder == TextEncoder::Multilingual, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Identifier of the model embedding texts with `encoder`.
+    pub fn text_model(&self, encoder: TextEncoder) -> &str {
+        match (encoder, &self.multilingual) {
+            (TextEncoder::Multilingual, Some(multilingual)) => multilingual.model(),
+            _ => self.backend.model(),
+        }
+    }
+
+    pub async fn text_embeddings(
+        &self,
+        encoder: TextEncoder,
+        texts: Vec<String>,
+    ) -> Result<EmbeddingsResponse> {
+        match (encoder, &self.multilingual) {
+            (TextEncoder::Multilingual, Some(multilingual)) => {
+                multilingual.text_embeddings(texts).await
+            }
+            _ => self.backend.text_embeddings(texts).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_embeddings_are_deterministic() {
+        let a = MockBackend::embed(b"cat");
+        assert_eq!(a, MockBackend::embed(b"cat"));
+        assert_ne!(a, MockBackend::embed(b"dog"));
+    }
+
+    #[test]
+    fn mock_embeddings_are_unit_vectors() {
+        for data in [&b""[..], b"cat", &[0; 64]] {
+            let embedding = MockBackend::embed(data);
+            assert_eq!(embedding.len(), EMBEDDING_DIM);
+            let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+            assert!((norm - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[tokio::test]
+    async fn mock_backend_embeds_each_input() {
+        let res = MockBackend
+            .text_embeddings(vec![String::from("cat"), String::from("dog")])
+            .await
+            .unwrap();
+        assert_eq!(res.embeddings.len(), 2);
+        assert_eq!(res.embeddings[0], MockBackend::embed(b"cat"));
+    }
+}
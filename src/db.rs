@@ -1,12 +1,42 @@
-use anyhow::Result;
-use entities::{images, prelude::*, sea_orm_active_enums::MediaType, users};
-use migration::{Alias, BinOper, Migrator, MigratorTrait, SimpleExpr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use entities::{
+    collection_subscriptions, collections, groups, images, prelude::*, query_embeddings,
+    reindex_failures, reindex_jobs, sea_orm_active_enums::MediaType, tags, translations, users,
+};
+use migration::{Alias, BinOper, Func, Migrator, MigratorTrait, OnConflict, Query, SimpleExpr};
+use rand::{distributions::Alphanumeric, Rng};
 use sea_orm::{
-    prelude::*, ActiveValue, ConnectOptions, Database, DatabaseConnection, EntityTrait,
-    FromQueryResult, IntoSimpleExpr, QueryOrder, QuerySelect,
+    prelude::*, ActiveValue, Condition, ConnectOptions, Database, DatabaseBackend,
+    DatabaseConnection, EntityTrait, FromQueryResult, IntoSimpleExpr, QueryOrder, QuerySelect,
+    Statement, TransactionTrait,
 };
 use tracing::log::LevelFilter;
 
+pub struct NewImage {
+    pub library: Library,
+    pub file_id: String,
+    /// Image the embedding was computed from, differs from `file_id` for
+    /// videos, animations and animated stickers.
+    pub preview_file_id: String,
+    pub unique_id: String,
+    pub media_type: MediaType,
+    pub embedding: Vec<f32>,
+    pub model: String,
+    pub caption: Option<String>,
+    pub caption_embedding: Option<Vec<f32>>,
+    pub phash: Option<i64>,
+}
+
+#[derive(FromQueryResult)]
+pub struct ImageWithCaption {
+    pub id: i32,
+    pub file_id: String,
+    pub preview_file_id: Option<String>,
+    pub caption: Option<String>,
+}
+
 #[derive(FromQueryResult)]
 pub struct ImageWithIds {
     pub id: i32,
@@ -14,11 +44,375 @@ pub struct ImageWithIds {
     pub file_id: String,
 }
 
+/// Image found by a similarity search.
+#[derive(FromQueryResult)]
+pub struct FoundImage {
+    pub id: i32,
+    pub media_type: MediaType,
+    pub file_id: String,
+    /// Distance to the query, blended with the caption distance.
+    pub distance: f64,
+    /// Position in the results, see [`SearchParams::score`].
+    pub score: f64,
+}
+
+impl From<FoundImage> for ImageWithIds {
+    fn from(image: FoundImage) -> Self {
+        Self {
+            id: image.id,
+            media_type: image.media_type,
+            file_id: image.file_id,
+        }
+    }
+}
+
+#[derive(FromQueryResult)]
+struct UsedImage {
+    id: i32,
+    media_type: MediaType,
+    file_id: String,
+    uses_count: i32,
+}
+
+/// Images shown in one response to an inline query.
+const PAGE_SIZE: usize = 50;
+
+/// Page of results and the cursor of the next one, if there's more.
+pub struct Page<T> {
+    pub images: Vec<T>,
+    pub next: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    /// Takes up to `PAGE_SIZE` images from `PAGE_SIZE + 1` fetched, an extra
+    /// image means there's a next page starting after the last one taken.
+    fn new(mut images: Vec<T>, cursor: impl Fn(&T) -> Cursor) -> Self {
+        let next = (images.len() > PAGE_SIZE).then(|| {
+            images.truncate(PAGE_SIZE);
+            cursor(&images[PAGE_SIZE - 1])
+        });
+        Self { images, next }
+    }
+}
+
+/// Position after the last image of a page, passed to Telegram as the
+/// inline query offset. Seeking from it instead of skipping rows keeps pages
+/// stable while images are saved or deleted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cursor {
+    Search { score: f64, id: i32, now: i64 },
+    MostUsed { uses: i32, id: i32 },
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        match self {
+            Self::Search { score, id, now } => format!("s{:x}.{id:x}.{now:x}", score.to_bits()),
+            Self::MostUsed { uses, id } => format!("u{uses:x}.{id:x}"),
+        }
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let (res, mut parts) = if let Some(rest) = cursor.strip_prefix('s') {
+            let mut parts = rest.split('.');
+            let res = Self::Search {
+                score: f64::from_bits(u64::from_str_radix(parts.next()?, 16).ok()?),
+                id: i32::from_str_radix(parts.next()?, 16).ok()?,
+                now: i64::from_str_radix(parts.next()?, 16).ok()?,
+            };
+            (res, parts)
+        } else if let Some(rest) = cursor.strip_prefix('u') {
+            let mut parts = rest.split('.');
+            let res = Self::MostUsed {
+                uses: i32::from_str_radix(parts.next()?, 16).ok()?,
+                id: i32::from_str_radix(parts.next()?, 16).ok()?,
+            };
+            (res, parts)
+        } else {
+            return None;
+        };
+        parts.next().is_none().then_some(res)
+    }
+}
+
+/// Narrows down which of the user's images a search returns.
+#[derive(Default)]
+pub struct ImageFilter {
+    /// Only images having every one of these tags.
+    pub tags: Vec<String>,
+    /// Only images from the collection with this name.
+    pub collection: Option<String>,
+    pub media_type: Option<MediaType>,
+}
+
+impl ImageFilter {
+    fn condition(&self) -> Condition {
+        let cond = match &self.media_type {
+            Some(media_type) => {
+                Condition::all().add(images::Column::MediaType.eq(media_type.clone()))
+            }
+            None => Condition::all(),
+        };
+        let cond = match &self.collection {
+            Some(name) => cond.add(
+                images::Column::CollectionId.in_subquery(
+                    Query::select()
+                        .column(collections::Column::Id)
+                        .from(Collections)
+                        .and_where(collections::Column::Name.eq(name))
+                        .to_owned(),
+                ),
+            ),
+            None => cond,
+        };
+        self.tags.iter().fold(cond, |cond, tag| {
+            cond.add(
+                images::Column::Id.in_subquery(
+                    Query::select()
+                        .column(tags::Column::ImageId)
+                        .from(Tags)
+                        .and_where(tags::Column::Name.eq(tag))
+                        .to_owned(),
+                ),
+            )
+        })
+    }
+}
+
+/// Owner of saved images: a user or a group chat curated by its admins.
+#[derive(Clone, Copy, Debug)]
+pub enum Library {
+    User(i64),
+    Group(i64),
+}
+
+impl Library {
+    fn user(self) -> Option<i64> {
+        match self {
+            Self::User(id) => Some(id),
+            Self::Group(_) => None,
+        }
+    }
+
+    fn group(self) -> Option<i64> {
+        match self {
+            Self::User(_) => None,
+            Self::Group(id) => Some(id),
+        }
+    }
+
+    /// Images saved to the library.
+    fn owns(self) -> Condition {
+        match self {
+            Self::User(id) => Condition::all().add(images::Column::UserId.eq(id)),
+            Self::Group(id) => Condition::all().add(images::Column::GroupId.eq(id)),
+        }
+    }
+
+    /// Images searchable in the library: for users also ones from
+    /// collections they're subscribed to.
+    fn visible(self) -> Condition {
+        match self {
+            Self::User(id) => Condition::any().add(images::Column::UserId.eq(id)).add(
+                images::Column::CollectionId.in_subquery(
+                    Query::select()
+                        .column(collection_subscriptions::Column::CollectionId)
+                        .from(CollectionSubscriptions)
+                        .and_where(collection_subscriptions::Column::UserId.eq(id))
+                        .to_owned(),
+                ),
+            ),
+            Self::Group(_) => self.owns(),
+        }
+    }
+}
+
+/// Tuning of similarity searches.
+pub struct SearchParams {
+    /// `hnsw.ef_search`, size of the candidate list of the HNSW index.
+    pub ef_search: Option<u32>,
+    /// `ivfflat.probes`, number of lists scanned if an IVFFlat index is used.
+    pub probes: Option<u32>,
+    /// `hnsw.iterative_scan`, keeps scanning the index until enough rows pass
+    /// the per-user filter, otherwise small libraries get few or no results
+    /// whenever the index is used. Turned off on pgvector older than 0.8.
+    pub iterative_scan: Option<String>,
+    /// Share of the caption similarity in the ranking of captioned images.
+    /// Off by default: any blended ranking can't use the HNSW index, so
+    /// enabling it trades search speed on large libraries for caption
+    /// matches.
+    pub caption_weight: f64,
+    /// Bonus per `ln(1 + uses_count)` subtracted from the distance. Off by
+    /// default for the same reason as `caption_weight`.
+    pub popularity_weight: f64,
+    /// Bonus subtracted from the distance of just saved images, halving
+    /// every `recency_half_life_days`.
+    pub recency_weight: f64,
+    pub recency_half_life_days: f64,
+    /// Images farther from the query are never returned, cosine distances
+    /// are at most 2, so 2 disables the cutoff.
+    pub max_distance: f64,
+}
+
+impl SearchParams {
+    /// Reads `HNSW_EF_SEARCH`, `IVFFLAT_PROBES`, `HNSW_ITERATIVE_SCAN`,
+    /// `CAPTION_WEIGHT`, `POPULARITY_WEIGHT`, `RECENCY_WEIGHT`,
+    /// `RECENCY_HALF_LIFE_DAYS` and `MAX_DISTANCE`.
+    pub fn from_env() -> Result<Self> {
+        let ef_search = match std::env::var("HNSW_EF_SEARCH") {
+            Ok(v) => Some(v.parse().context("invalid HNSW_EF_SEARCH")?),
+            Err(_) => None,
+        };
+        let probes = match std::env::var("IVFFLAT_PROBES") {
+            Ok(v) => Some(v.parse().context("invalid IVFFLAT_PROBES")?),
+            Err(_) => None,
+        };
+        let iterative_scan = match std::env::var("HNSW_ITERATIVE_SCAN") {
+            Ok(v) if v == "off" => None,
+            Ok(v) if ["strict_order", "relaxed_order"].contains(&v.as_str()) => Some(v),
+            Ok(v) => bail!("invalid HNSW_ITERATIVE_SCAN: {v}"),
+            Err(_) => Some(String::from("strict_order")),
+        };
+        let caption_weight = match std::env::var("CAPTION_WEIGHT") {
+            Ok(v) => v.parse().context("invalid CAPTION_WEIGHT")?,
+            Err(_) => 0.0,
+        };
+        if !(0.0..=1.0).contains(&caption_weight) {
+            bail!("CAPTION_WEIGHT must be between 0 and 1");
+        }
+        let popularity_weight = match std::env::var("POPULARITY_WEIGHT") {
+            Ok(v) => v.parse().context("invalid POPULARITY_WEIGHT")?,
+            Err(_) => 0.0,
+        };
+        let recency_weight = match std::env::var("RECENCY_WEIGHT") {
+            Ok(v) => v.parse().context("invalid RECENCY_WEIGHT")?,
+            Err(_) => 0.0,
+        };
+        if popularity_weight < 0.0 || recency_weight < 0.0 {
+            bail!("POPULARITY_WEIGHT and RECENCY_WEIGHT must not be negative");
+        }
+        let recency_half_life_days = match std::env::var("RECENCY_HALF_LIFE_DAYS") {
+            Ok(v) => v.parse().context("invalid RECENCY_HALF_LIFE_DAYS")?,
+            Err(_) => 30.0,
+        };
+        if recency_half_life_days <= 0.0 {
+            bail!("RECENCY_HALF_LIFE_DAYS must be positive");
+        }
+        let max_distance = match std::env::var("MAX_DISTANCE") {
+            Ok(v) => v.parse().context("invalid MAX_DISTANCE")?,
+            Err(_) => 0.9,
+        };
+        Ok(Self {
+            ef_search,
+            probes,
+            iterative_scan,
+            caption_weight,
+            popularity_weight,
+            recency_weight,
+            recency_half_life_days,
+            max_distance,
+        })
+    }
+
+    /// Cosine distance between the query and an image, blended with the
+    /// distance to its caption if it has one.
+    fn distance(&self, embedding: Vec<f32>) -> SimpleExpr {
+        let query = SimpleExpr::from(embedding).cast_as(Alias::new("vector"));
+        let image_distance = images::Column::Embedding
+            .into_simple_expr()
+            .binary(BinOper::Custom("<=>"), query.clone());
+        if self.caption_weight == 0.0 {
+            return image_distance;
+        }
+
+        let caption_distance = images::Column::CaptionEmbedding
+            .into_simple_expr()
+            .binary(BinOper::Custom("<=>"), query);
+        Expr::val(1.0 - self.caption_weight)
+            .mul(image_distance.clone())
+            .add(
+                Expr::val(self.caption_weight)
+                    .mul(Func::coalesce([caption_distance, image_distance])),
+            )
+    }
+
+    /// Ranking of search results, lower is better: the distance minus
+    /// bonuses for popular and recently saved images. Ages are counted from
+    /// `now` (Unix time), so scores don't change between pages.
+    fn score(&self, embedding: Vec<f32>, now: i64) -> SimpleExpr {
+        let mut score = self.distance(embedding);
+        if self.popularity_weight != 0.0 {
+            score = score.sub(
+                Expr::val(self.popularity_weight)
+                    .mul(Expr::cust(r#"ln(1 + "images"."uses_count")"#)),
+            );
+        }
+        if self.recency_weight != 0.0 {
+            score = score.sub(Expr::val(self.recency_weight).mul(Expr::cust_with_values(
+                r#"power(0.5, extract(epoch from to_timestamp($1) - "images"."creation_time") / $2)"#,
+                [
+                    sea_orm::Value::from(now),
+                    sea_orm::Value::from(self.recency_half_life_days * 86400.0),
+                ],
+            )));
+        }
+        score
+    }
+
+    fn statements(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        if let Some(ef_search) = self.ef_search {
+            res.push(format!("SET LOCAL hnsw.ef_search = {ef_search}"));
+        }
+        if let Some(probes) = self.probes {
+            res.push(format!("SET LOCAL ivfflat.probes = {probes}"));
+        }
+        if let Some(iterative_scan) = &self.iterative_scan {
+            res.push(format!("SET LOCAL hnsw.iterative_scan = {iterative_scan}"));
+        }
+        res
+    }
+}
+
+/// Maximal number of differing perceptual hash bits for images to be
+/// considered near-identical if `PHASH_MAX_DISTANCE` is unset.
+const DEFAULT_PHASH_MAX_DISTANCE: u32 = 6;
+
+/// Maximal cosine distance between embeddings of near-duplicate images if
+/// `DUPLICATE_MAX_DISTANCE` is unset.
+const DEFAULT_DUPLICATE_MAX_DISTANCE: f64 = 0.08;
+
+/// Returns the installed pgvector version as (major, minor).
+async fn pgvector_version(dc: &DatabaseConnection) -> Result<(u32, u32)> {
+    let row = dc
+        .query_one(Statement::from_string(
+            DatabaseBackend::Postgres,
+            "SELECT extversion FROM pg_extension WHERE extname = 'vector'",
+        ))
+        .await?
+        .context("pgvector isn't installed")?;
+    let version: String = row.try_get("", "extversion")?;
+    let mut parts = version.split('.').map(|p| p.parse::<u32>().unwrap_or(0));
+    Ok((parts.next().unwrap_or(0), parts.next().unwrap_or(0)))
+}
+
+/// Nearest images of each image checked for being its near-duplicates.
+const DUPLICATE_NEIGHBORS: usize = 10;
+
 pub struct Db {
     dc: DatabaseConnection,
+    search_params: SearchParams,
+    phash_max_distance: u32,
+    duplicate_max_distance: f64,
 }
 
 impl Db {
+    /// Whether captions take part in the ranking, otherwise they aren't
+    /// worth embedding.
+    pub fn captions_enabled(&self) -> bool {
+        self.search_params.caption_weight != 0.0
+    }
+
     pub async fn new() -> Result<Self> {
         let db_url = std::env::var("DATABASE_URL")?;
 
@@ -28,7 +422,32 @@ impl Db {
 
         let dc = Database::connect(conn_options).await?;
         Migrator::up(&dc, None).await?;
-        Ok(Self { dc })
+        let mut search_params = SearchParams::from_env()?;
+        if search_params.iterative_scan.is_some() {
+            let (major, minor) = pgvector_version(&dc).await?;
+            if (major, minor) < (0, 8) {
+                tracing::warn!(
+                    "pgvector {major}.{minor} doesn't support hnsw.iterative_scan, searches in \
+                    small libraries may miss results until it's upgraded to 0.8"
+                );
+                search_params.iterative_scan = None;
+            }
+        }
+        let phash_max_distance = match std::env::var("PHASH_MAX_DISTANCE") {
+            Ok(distance) => distance.parse().context("invalid PHASH_MAX_DISTANCE")?,
+            Err(_) => DEFAULT_PHASH_MAX_DISTANCE,
+        };
+        let duplicate_max_distance = match std::env::var("DUPLICATE_MAX_DISTANCE") {
+            Ok(distance) => distance.parse().context("invalid DUPLICATE_MAX_DISTANCE")?,
+            Err(_) => DEFAULT_DUPLICATE_MAX_DISTANCE,
+        };
+
+        Ok(Self {
+            dc,
+            search_params,
+            phash_max_distance,
+            duplicate_max_distance,
+        })
     }
 
     pub async fn update_user(&self, id: i64) -> Result<()> {
@@ -51,6 +470,40 @@ impl Db {
         Ok(())
     }
 
+    pub async fn update_group(&self, id: i64) -> Result<()> {
+        let group = groups::ActiveModel {
+            id: ActiveValue::Set(id),
+            ..Default::default()
+        };
+        Groups::insert(group)
+            .on_conflict(
+                OnConflict::column(groups::Column::Id)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.dc)
+            .await?;
+        Ok(())
+    }
+
+    /// Sets the group library searched with the `g:` prefix.
+    pub async fn select_group(&self, user: i64, group: i64) -> Result<()> {
+        Users::update_many()
+            .col_expr(users::Column::GroupId, Expr::value(group))
+            .filter(users::Column::Id.eq(user))
+            .exec(&self.dc)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_selected_group(&self, user: i64) -> Result<Option<i64>> {
+        let res = Users::find_by_id(user)
+            .one(&self.dc)
+            .await?
+            .and_then(|u| u.group_id);
+        Ok(res)
+    }
+
     pub async fn increment_image_uses(&self, image: i32, user: i64) -> Result<()> {
         Images::update_many()
             .col_expr(
@@ -58,104 +511,784 @@ impl Db {
                 images::Column::UsesCount.into_simple_expr().add(1),
             )
             .filter(images::Column::Id.eq(image))
-            .filter(images::Column::UserId.eq(user))
+            // Group images are shared, so any member's use counts.
+            .filter(
+                Condition::any()
+                    .add(images::Column::UserId.eq(user))
+                    .add(images::Column::GroupId.is_not_null()),
+            )
             .exec(&self.dc)
             .await?;
         Ok(())
     }
 
-    pub async fn create_image(
-        &self,
-        user: i64,
-        embedding: Vec<f32>,
-        file_id: String,
-        unique_id: String,
-        media_type: MediaType,
-    ) -> Result<()> {
+    /// Saves the image and returns its id.
+    pub async fn create_image(&self, image: NewImage) -> Result<i32> {
         let image = images::ActiveModel {
-            user_id: ActiveValue::Set(user),
-            media_type: ActiveValue::Set(media_type),
-            file_id: ActiveValue::Set(file_id),
-            unique_id: ActiveValue::Set(unique_id),
-            embedding: ActiveValue::Set(embedding),
+            user_id: ActiveValue::Set(image.library.user()),
+            group_id: ActiveValue::Set(image.library.group()),
+            media_type: ActiveValue::Set(image.media_type),
+            file_id: ActiveValue::Set(image.file_id),
+            preview_file_id: ActiveValue::Set(Some(image.preview_file_id)),
+            unique_id: ActiveValue::Set(image.unique_id),
+            embedding: ActiveValue::Set(image.embedding),
+            embedding_model: ActiveValue::Set(image.model),
+            caption: ActiveValue::Set(image.caption),
+            caption_embedding: ActiveValue::Set(image.caption_embedding),
+            phash: ActiveValue::Set(image.phash),
             ..Default::default()
         };
-        Images::insert(image).exec(&self.dc).await?;
-        Ok(())
+        let res = Images::insert(image).exec(&self.dc).await?;
+        Ok(res.last_insert_id)
     }
 
-    pub async fn delete_image(&self, user: i64, unique_id: String) -> Result<bool> {
-        let res = Images::delete_many()
+    /// Returns the id of the user's image with the perceptual hash closest
+    /// to `phash`, if it differs in at most `PHASH_MAX_DISTANCE` bits.
+    pub async fn find_similar_image(&self, user: i64, phash: i64) -> Result<Option<i32>> {
+        let distance = Expr::cust_with_values("bit_count(CAST(phash # $1 AS bit(64)))", [phash]);
+        let res = Images::find()
+            .select_only()
+            .column(images::Column::Id)
+            .filter(images::Column::UserId.eq(user))
+            .filter(images::Column::DeletedAt.is_null())
+            .filter(images::Column::Phash.is_not_null())
+            .filter(Expr::expr(distance.clone()).lte(self.phash_max_distance))
+            .order_by_asc(distance)
+            .into_tuple()
+            .one(&self.dc)
+            .await?;
+        Ok(res)
+    }
+
+    /// Returns pairs of the user's images embedded with `model` whose
+    /// embeddings are closer than `DUPLICATE_MAX_DISTANCE`, the smaller id
+    /// first. Only the `DUPLICATE_NEIGHBORS` nearest images of each image are
+    /// compared, so the vector index serves the lookups.
+    pub async fn get_duplicate_pairs(&self, user: i64, model: &str) -> Result<Vec<(i32, i32)>> {
+        let txn = self.dc.begin().await?;
+        for statement in self.search_params.statements() {
+            txn.execute(Statement::from_string(DatabaseBackend::Postgres, statement))
+                .await?;
+        }
+        let rows = txn
+            .query_all(Statement::from_sql_and_values(
+                DatabaseBackend::Postgres,
+                r#"SELECT DISTINCT LEAST(a.id, b.id) AS a, GREATEST(a.id, b.id) AS b
+                FROM images a
+                CROSS JOIN LATERAL (
+                    SELECT n.id, n.embedding <=> a.embedding AS distance
+                    FROM images n
+                    WHERE n.user_id = a.user_id AND n.id <> a.id
+                        AND n.deleted_at IS NULL AND n.embedding_model = $2
+                    ORDER BY n.embedding <=> a.embedding
+                    LIMIT $4
+                ) b
+                WHERE a.user_id = $1 AND a.deleted_at IS NULL AND a.embedding_model = $2
+                    AND b.distance < $3"#,
+                [
+                    user.into(),
+                    model.into(),
+                    self.duplicate_max_distance.into(),
+                    (DUPLICATE_NEIGHBORS as i64).into(),
+                ],
+            ))
+            .await?;
+        txn.commit().await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get("", "a")?, row.try_get("", "b")?)))
+            .collect()
+    }
+
+    pub async fn get_images(&self, user: i64, ids: Vec<i32>) -> Result<Vec<ImageWithIds>> {
+        let res = Images::find()
+            .select_only()
+            .column(images::Column::Id)
+            .column(images::Column::MediaType)
+            .column(images::Column::FileId)
             .filter(images::Column::UserId.eq(user))
+            .filter(images::Column::Id.is_in(ids))
+            .filter(images::Column::DeletedAt.is_null())
+            .order_by_asc(images::Column::Id)
+            .into_model::<ImageWithIds>()
+            .all(&self.dc)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn get_image_id(&self, library: Library, unique_id: String) -> Result<Option<i32>> {
+        let res = Images::find()
+            .select_only()
+            .column(images::Column::Id)
+            .filter(library.owns())
             .filter(images::Column::UniqueId.eq(unique_id))
+            .filter(images::Column::DeletedAt.is_null())
+            .into_tuple()
+            .one(&self.dc)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn add_tags(&self, image: i32, tags: Vec<String>) -> Result<()> {
+        let tags = tags.into_iter().map(|name| tags::ActiveModel {
+            image_id: ActiveValue::Set(image),
+            name: ActiveValue::Set(name),
+            ..Default::default()
+        });
+        Tags::insert_many(tags)
+            .on_conflict(
+                OnConflict::columns([tags::Column::ImageId, tags::Column::Name])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.dc)
+            .await?;
+        Ok(())
+    }
+
+    /// Moves the image to the trash, returns `false` if there's no such image.
+    pub async fn delete_image(&self, library: Library, id: i32) -> Result<bool> {
+        let res = Images::update_many()
+            .col_expr(images::Column::DeletedAt, Expr::current_timestamp().into())
+            .filter(images::Column::Id.eq(id))
+            .filter(library.owns())
+            .filter(images::Column::DeletedAt.is_null())
             .exec(&self.dc)
             .await?;
         Ok(res.rows_affected >= 1)
     }
 
-    pub async fn search_images(
+    /// Takes the image out of the trash, returns `false` if it isn't there.
+    pub async fn restore_image(&self, library: Library, id: i32) -> Result<bool> {
+        let res = Images::update_many()
+            .col_expr(
+                images::Column::DeletedAt,
+                Expr::value(Option::<DateTime>::None),
+            )
+            .filter(images::Column::Id.eq(id))
+            .filter(library.owns())
+            .filter(images::Column::DeletedAt.is_not_null())
+            .exec(&self.dc)
+            .await?;
+        Ok(res.rows_affected >= 1)
+    }
+
+    pub async fn get_deleted_image_id(
         &self,
-        user: i64,
-        embedding: Vec<f32>,
-        offset: Option<u64>,
-    ) -> Result<Vec<ImageWithIds>> {
+        library: Library,
+        unique_id: String,
+    ) -> Result<Option<i32>> {
+        let res = Images::find()
+            .select_only()
+            .column(images::Column::Id)
+            .filter(library.owns())
+            .filter(images::Column::UniqueId.eq(unique_id))
+            .filter(images::Column::DeletedAt.is_not_null())
+            .into_tuple()
+            .one(&self.dc)
+            .await?;
+        Ok(res)
+    }
+
+    /// Returns the most recently deleted images of the user.
+    pub async fn get_deleted_images(&self, user: i64, limit: u64) -> Result<Vec<ImageWithIds>> {
         let res = Images::find()
             .select_only()
             .column(images::Column::Id)
             .column(images::Column::MediaType)
             .column(images::Column::FileId)
             .filter(images::Column::UserId.eq(user))
-            .order_by_asc(images::Column::Embedding.into_simple_expr().binary(
-                BinOper::Custom("<=>"),
-                SimpleExpr::from(embedding).cast_as(Alias::new("vector")),
-            ))
-            .limit(51)
-            .offset(offset)
+            .filter(images::Column::DeletedAt.is_not_null())
+            .order_by_desc(images::Column::DeletedAt)
+            .limit(limit)
             .into_model::<ImageWithIds>()
             .all(&self.dc)
             .await?;
         Ok(res)
     }
 
+    /// Permanently deletes images that have been in the trash for longer
+    /// than `days`.
+    pub async fn purge_deleted_images(&self, days: u32) -> Result<u64> {
+        let res = Images::delete_many()
+            .filter(
+                Expr::col(images::Column::DeletedAt)
+                    .lt(Expr::cust(format!("now() - interval '{days} days'"))),
+            )
+            .exec(&self.dc)
+            .await?;
+        Ok(res.rows_affected)
+    }
+
+    /// Deletes query embeddings older than `ttl_days`, returns how many.
+    pub async fn purge_query_embeddings(&self, ttl_days: u32) -> Result<u64> {
+        let res = QueryEmbeddings::delete_many()
+            .filter(
+                Expr::col(query_embeddings::Column::CreationTime)
+                    .lt(Expr::cust(format!("now() - interval '{ttl_days} days'"))),
+            )
+            .exec(&self.dc)
+            .await?;
+        Ok(res.rows_affected)
+    }
+
+    /// Images closer than `MAX_DISTANCE` to the embedding, best first.
+    /// Without `cutoff` every image passing the filter is returned.
+    pub async fn search_images(
+        &self,
+        library: Library,
+        embedding: Vec<f32>,
+        model: &str,
+        filter: &ImageFilter,
+        cutoff: bool,
+        cursor: Option<Cursor>,
+    ) -> Result<Page<FoundImage>> {
+        let (after, now) = match cursor {
+            Some(Cursor::Search { score, id, now }) => (Some((score, id)), now),
+            _ => (
+                None,
+                SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+            ),
+        };
+
+        // SET LOCAL only lasts until the end of the transaction.
+        let txn = self.dc.begin().await?;
+        for statement in self.search_params.statements() {
+            txn.execute(Statement::from_string(DatabaseBackend::Postgres, statement))
+                .await?;
+        }
+
+        let distance = self.search_params.distance(embedding.clone());
+        let score = self.search_params.score(embedding, now);
+        let mut query = Images::find()
+            .select_only()
+            .column(images::Column::Id)
+            .column(images::Column::MediaType)
+            .column(images::Column::FileId)
+            .column_as(distance.clone(), "distance")
+            .column_as(score.clone(), "score")
+            .filter(library.visible())
+            .filter(images::Column::EmbeddingModel.eq(model))
+            .filter(images::Column::DeletedAt.is_null())
+            .filter(filter.condition());
+        if cutoff {
+            query = query.filter(Expr::expr(distance).lte(self.search_params.max_distance));
+        }
+        // Ordering by the score alone keeps the HNSW index usable, ties in
+        // it are practically only duplicate embeddings.
+        if let Some((score_after, id_after)) = after {
+            query = query.filter(
+                Condition::any()
+                    .add(Expr::expr(score.clone()).gt(score_after))
+                    .add(
+                        Condition::all()
+                            .add(Expr::expr(score.clone()).eq(score_after))
+                            .add(images::Column::Id.gt(id_after)),
+                    ),
+            );
+        }
+        let res = query
+            .order_by_asc(score)
+            .limit(PAGE_SIZE as u64 + 1)
+            .into_model::<FoundImage>()
+            .all(&txn)
+            .await?;
+
+        txn.commit().await?;
+        Ok(Page::new(res, |image| Cursor::Search {
+            score: image.score,
+            id: image.id,
+            now,
+        }))
+    }
+
     pub async fn get_most_used_images(
         &self,
-        user: i64,
-        offset: Option<u64>,
-    ) -> Result<Vec<ImageWithIds>> {
-        let res = Images::find()
+        library: Library,
+        filter: &ImageFilter,
+        cursor: Option<Cursor>,
+    ) -> Result<Page<ImageWithIds>> {
+        let mut query = Images::find()
             .select_only()
             .column(images::Column::Id)
             .column(images::Column::MediaType)
             .column(images::Column::FileId)
-            .filter(images::Column::UserId.eq(user))
+            .column(images::Column::UsesCount)
+            .filter(library.visible())
+            .filter(images::Column::DeletedAt.is_null())
+            .filter(filter.condition());
+        if let Some(Cursor::MostUsed { uses, id }) = cursor {
+            query = query.filter(
+                Expr::tuple([
+                    images::Column::UsesCount.into_simple_expr(),
+                    images::Column::Id.into_simple_expr(),
+                ])
+                .lt(Expr::tuple([uses.into(), id.into()])),
+            );
+        }
+        // Ids grow with creation time, so equally used images are newest
+        // first.
+        let res = query
             .order_by_desc(images::Column::UsesCount)
-            .order_by_desc(images::Column::CreationTime)
-            .limit(51)
-            .offset(offset)
-            .into_model::<ImageWithIds>()
+            .order_by_desc(images::Column::Id)
+            .limit(PAGE_SIZE as u64 + 1)
+            .into_model::<UsedImage>()
             .all(&self.dc)
             .await?;
-        Ok(res)
+
+        let page = Page::new(res, |image| Cursor::MostUsed {
+            uses: image.uses_count,
+            id: image.id,
+        });
+        Ok(Page {
+            images: page
+                .images
+                .into_iter()
+                .map(|image| ImageWithIds {
+                    id: image.id,
+                    media_type: image.media_type,
+                    file_id: image.file_id,
+                })
+                .collect(),
+            next: page.next,
+        })
     }
 
-    pub async fn get_all_images(&self) -> Result<Vec<ImageWithIds>> {
+    /// Returns up to `limit` images with ids greater than `after` whose
+    /// embeddings weren't produced by `model`, in id order.
+    pub async fn get_stale_images(
+        &self,
+        model: &str,
+        after: i32,
+        limit: u64,
+    ) -> Result<Vec<ImageWithCaption>> {
         let res = Images::find()
             .select_only()
             .column(images::Column::Id)
-            .column(images::Column::MediaType)
             .column(images::Column::FileId)
-            .into_model::<ImageWithIds>()
+            .column(images::Column::PreviewFileId)
+            .column(images::Column::Caption)
+            .filter(images::Column::Id.gt(after))
+            .filter(images::Column::EmbeddingModel.ne(model))
+            .filter(images::Column::DeletedAt.is_null())
+            .order_by_asc(images::Column::Id)
+            .limit(limit)
+            .into_model::<ImageWithCaption>()
             .all(&self.dc)
             .await?;
         Ok(res)
     }
 
-    pub async fn update_image(&self, id: i32, embedding: Vec<f32>) -> Result<()> {
+    pub async fn update_image(
+        &self,
+        id: i32,
+        embedding: Vec<f32>,
+        caption_embedding: Option<Vec<f32>>,
+        model: &str,
+    ) -> Result<()> {
         Images::update_many()
             .col_expr(images::Column::Embedding, embedding.into())
+            .col_expr(images::Column::CaptionEmbedding, caption_embedding.into())
+            .col_expr(images::Column::EmbeddingModel, model.into())
             .filter(images::Column::Id.eq(id))
             .exec(&self.dc)
             .await?;
         Ok(())
     }
+
+    pub async fn count_stale_images(&self, model: &str, after: i32) -> Result<u64> {
+        let res = Images::find()
+            .filter(images::Column::Id.gt(after))
+            .filter(images::Column::EmbeddingModel.ne(model))
+            .filter(images::Column::DeletedAt.is_null())
+            .count(&self.dc)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn create_reindex_job(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+    ) -> Result<reindex_jobs::Model> {
+        let job = reindex_jobs::ActiveModel {
+            chat_id: ActiveValue::Set(chat_id),
+            message_id: ActiveValue::Set(message_id),
+            ..Default::default()
+        };
+        Ok(ReindexJobs::insert(job)
+            .exec_with_returning(&self.dc)
+            .await?)
+    }
+
+    pub async fn get_unfinished_reindex_jobs(&self) -> Result<Vec<reindex_jobs::Model>> {
+        let res = ReindexJobs::find()
+            .filter(reindex_jobs::Column::FinishTime.is_null())
+            .order_by_asc(reindex_jobs::Column::Id)
+            .all(&self.dc)
+            .await?;
+        Ok(res)
+    }
+
+    /// Saves the checkpoint: every image up to `last_image_id` is processed.
+    pub async fn update_reindex_job(
+        &self,
+        id: i32,
+        last_image_id: i32,
+        processed: i32,
+        failed: i32,
+    ) -> Result<()> {
+        ReindexJobs::update_many()
+            .col_expr(reindex_jobs::Column::LastImageId, last_image_id.into())
+            .col_expr(reindex_jobs::Column::Processed, processed.into())
+            .col_expr(reindex_jobs::Column::Failed, failed.into())
+            .filter(reindex_jobs::Column::Id.eq(id))
+            .exec(&self.dc)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn finish_reindex_job(&self, id: i32) -> Result<()> {
+        ReindexJobs::update_many()
+            .col_expr(
+                reindex_jobs::Column::FinishTime,
+                Expr::current_timestamp().into(),
+            )
+            .filter(reindex_jobs::Column::Id.eq(id))
+            .exec(&self.dc)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn add_reindex_failure(&self, job: i32, image: i32, error: String) -> Result<()> {
+        let failure = reindex_failures::ActiveModel {
+            job_id: ActiveValue::Set(job),
+            image_id: ActiveValue::Set(image),
+            error: ActiveValue::Set(error),
+            ..Default::default()
+        };
+        ReindexFailures::insert(failure).exec(&self.dc).await?;
+        Ok(())
+    }
+
+    /// Creates a collection, returns `false` if the user already has one
+    /// with this name.
+    pub async fn create_collection(&self, user: i64, name: String) -> Result<bool> {
+        let collection = collections::ActiveModel {
+            user_id: ActiveValue::Set(user),
+            name: ActiveValue::Set(name),
+            ..Default::default()
+        };
+        let res = Collections::insert(collection)
+            .on_conflict(
+                OnConflict::columns([collections::Column::UserId, collections::Column::Name])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.dc)
+            .await?;
+        Ok(res >= 1)
+    }
+
+    pub async fn get_collections(&self, user: i64) -> Result<Vec<collections::Model>> {
+        let res = Collections::find()
+            .filter(collections::Column::UserId.eq(user))
+            .order_by_asc(collections::Column::Name)
+            .all(&self.dc)
+            .await?;
+        Ok(res)
+    }
+
+    /// Names of the user's own and subscribed collections, which can be
+    /// searched with a `name:` prefix.
+    pub async fn get_collection_names(&self, user: i64) -> Result<Vec<String>> {
+        let res = Collections::find()
+            .select_only()
+            .column(collections::Column::Name)
+            .filter(
+                Condition::any()
+                    .add(collections::Column::UserId.eq(user))
+                    .add(
+                        collections::Column::Id.in_subquery(
+                            Query::select()
+                                .column(collection_subscriptions::Column::CollectionId)
+                                .from(CollectionSubscriptions)
+                                .and_where(collection_subscriptions::Column::UserId.eq(user))
+                                .to_owned(),
+                        ),
+                    ),
+            )
+            .distinct()
+            .into_tuple()
+            .all(&self.dc)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn get_collection(
+        &self,
+        user: i64,
+        name: String,
+    ) -> Result<Option<collections::Model>> {
+        let res = Collections::find()
+            .filter(collections::Column::UserId.eq(user))
+            .filter(collections::Column::Name.eq(name))
+            .one(&self.dc)
+            .await?;
+        Ok(res)
+    }
+
+    /// Moves the image to the user's collection or out of any collection if
+    /// `collection` is `None`. Returns `false` if there's no such image or
+    /// collection.
+    pub async fn move_image(&self, user: i64, image: i32, collection: Option<i32>) -> Result<bool> {
+        if let Some(collection) = collection {
+            let owned = Collections::find_by_id(collection)
+                .filter(collections::Column::UserId.eq(user))
+                .one(&self.dc)
+                .await?;
+            if owned.is_none() {
+                return Ok(false);
+            }
+        }
+
+        let res = Images::update_many()
+            .col_expr(images::Column::CollectionId, Expr::value(collection))
+            .filter(images::Column::Id.eq(image))
+            .filter(images::Column::UserId.eq(user))
+            .filter(images::Column::DeletedAt.is_null())
+            .exec(&self.dc)
+            .await?;
+        Ok(res.rows_affected >= 1)
+    }
+
+    /// Returns the share token of the user's collection, generating one if
+    /// it isn't shared yet. `None` if there's no such collection.
+    pub async fn share_collection(&self, user: i64, name: String) -> Result<Option<String>> {
+        let Some(collection) = self.get_collection(user, name).await? else {
+            return Ok(None);
+        };
+        if let Some(token) = collection.share_token {
+            return Ok(Some(token));
+        }
+
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(24)
+            .map(char::from)
+            .collect();
+        Collections::update_many()
+            .col_expr(collections::Column::ShareToken, Expr::value(token.clone()))
+            .filter(collections::Column::Id.eq(collection.id))
+            .exec(&self.dc)
+            .await?;
+        Ok(Some(token))
+    }
+
+    /// Invalidates the share link of the user's collection and removes all
+    /// its subscribers. Returns `false` if there's no such shared collection.
+    pub async fn unshare_collection(&self, user: i64, name: String) -> Result<bool> {
+        let Some(collection) = self.get_collection(user, name).await? else {
+            return Ok(false);
+        };
+        if collection.share_token.is_none() {
+            return Ok(false);
+        }
+
+        let txn = self.dc.begin().await?;
+        Collections::update_many()
+            .col_expr(
+                collections::Column::ShareToken,
+                Expr::value(Option::<String>::None),
+            )
+            .filter(collections::Column::Id.eq(collection.id))
+            .exec(&txn)
+            .await?;
+        CollectionSubscriptions::delete_many()
+            .filter(collection_subscriptions::Column::CollectionId.eq(collection.id))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(true)
+    }
+
+    /// Subscribes the user to the collection shared with `token`, returns
+    /// the collection or `None` if the link is invalid. Users aren't
+    /// subscribed to their own collections.
+    pub async fn subscribe(&self, user: i64, token: String) -> Result<Option<collections::Model>> {
+        let Some(collection) = Collections::find()
+            .filter(collections::Column::ShareToken.eq(token))
+            .one(&self.dc)
+            .await?
+        else {
+            return Ok(None);
+        };
+        if collection.user_id == user {
+            return Ok(Some(collection));
+        }
+
+        let subscription = collection_subscriptions::ActiveModel {
+            user_id: ActiveValue::Set(user),
+            collection_id: ActiveValue::Set(collection.id),
+            ..Default::default()
+        };
+        CollectionSubscriptions::insert(subscription)
+            .on_conflict(
+                OnConflict::columns([
+                    collection_subscriptions::Column::UserId,
+                    collection_subscriptions::Column::CollectionId,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec_without_returning(&self.dc)
+            .await?;
+        Ok(Some(collection))
+    }
+
+    pub async fn get_subscriptions(&self, user: i64) -> Result<Vec<collections::Model>> {
+        let res = Collections::find()
+            .inner_join(CollectionSubscriptions)
+            .filter(collection_subscriptions::Column::UserId.eq(user))
+            .order_by_asc(collections::Column::Name)
+            .all(&self.dc)
+            .await?;
+        Ok(res)
+    }
+
+    /// Returns `false` if the user isn't subscribed to the collection.
+    pub async fn unsubscribe(&self, user: i64, collection: i32) -> Result<bool> {
+        let res = CollectionSubscriptions::delete_many()
+            .filter(collection_subscriptions::Column::UserId.eq(user))
+            .filter(collection_subscriptions::Column::CollectionId.eq(collection))
+            .exec(&self.dc)
+            .await?;
+        Ok(res.rows_affected >= 1)
+    }
+
+    pub async fn get_translation(
+        &self,
+        provider: &str,
+        source_language: &str,
+        source: String,
+    ) -> Result<Option<String>> {
+        let res =
+            Translations::find_by_id((source, provider.to_owned(), source_language.to_owned()))
+                .one(&self.dc)
+                .await?;
+        Ok(res.map(|t| t.translation))
+    }
+
+    pub async fn save_translation(
+        &self,
+        provider: &str,
+        source_language: &str,
+        source: String,
+        translation: String,
+    ) -> Result<()> {
+        let translation = translations::ActiveModel {
+            source: ActiveValue::Set(source),
+            translation: ActiveValue::Set(translation),
+            provider: ActiveValue::Set(provider.to_owned()),
+            source_language: ActiveValue::Set(source_language.to_owned()),
+            ..Default::default()
+        };
+        Translations::insert(translation)
+            .on_conflict(
+                OnConflict::columns([
+                    translations::Column::Provider,
+                    translations::Column::SourceLanguage,
+                    translations::Column::Source,
+                ])
+                .update_column(translations::Column::Translation)
+                .to_owned(),
+            )
+            .exec_without_returning(&self.dc)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the embedding unless it's older than `ttl_days`.
+    pub async fn get_query_embedding(
+        &self,
+        model: &str,
+        provider: &str,
+        query: &str,
+        ttl_days: u32,
+    ) -> Result<Option<Vec<f32>>> {
+        let res =
+            QueryEmbeddings::find_by_id((model.to_owned(), query.to_owned(), provider.to_owned()))
+                .filter(
+                    Expr::col(query_embeddings::Column::CreationTime)
+                        .gte(Expr::cust(format!("now() - interval '{ttl_days} days'"))),
+                )
+                .one(&self.dc)
+                .await?;
+        Ok(res.map(|q| q.embedding))
+    }
+
+    pub async fn save_query_embedding(
+        &self,
+        model: String,
+        provider: &str,
+        query: String,
+        embedding: Vec<f32>,
+    ) -> Result<()> {
+        let query_embedding = query_embeddings::ActiveModel {
+            model: ActiveValue::Set(model),
+            provider: ActiveValue::Set(provider.to_owned()),
+            query: ActiveValue::Set(query),
+            embedding: ActiveValue::Set(embedding),
+            ..Default::default()
+        };
+        QueryEmbeddings::insert(query_embedding)
+            .on_conflict(
+                OnConflict::columns([
+                    query_embeddings::Column::Model,
+                    query_embeddings::Column::Provider,
+                    query_embeddings::Column::Query,
+                ])
+                .update_columns([
+                    query_embeddings::Column::Embedding,
+                    query_embeddings::Column::CreationTime,
+                ])
+                .to_owned(),
+            )
+            .exec_without_returning(&self.dc)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip() {
+        for cursor in [
+            Cursor::Search {
+                score: -0.123456789,
+                id: 42,
+                now: 1_700_000_000,
+            },
+            Cursor::Search {
+                score: 1.5,
+                id: i32::MAX,
+                now: 0,
+            },
+            Cursor::MostUsed { uses: 0, id: 1 },
+            Cursor::MostUsed { uses: 1234, id: 7 },
+        ] {
+            assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        }
+    }
+
+    #[test]
+    fn cursor_rejects_malformed_offsets() {
+        for offset in [
+            "", "50", "s", "u1", "u1.2.3", "uz.1", "s1.2", "s1.2.3.4", "x1.2", "ы1.2",
+        ] {
+            assert_eq!(Cursor::decode(offset), None, "{offset:?}");
+        }
+    }
 }
@@ -0,0 +1,76 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+/// Wait before embedding a query if `INLINE_DEBOUNCE_MS` is unset.
+const DEFAULT_DELAY_MS: u64 = 300;
+
+/// Telegram sends an inline query on every keystroke, only the latest query
+/// of each user is worth sending to the model server.
+pub struct Debouncer {
+    delay: Duration,
+    /// Sequence number of the latest query of each user.
+    latest: Mutex<HashMap<i64, u64>>,
+    next: AtomicU64,
+}
+
+impl Debouncer {
+    pub fn from_env() -> Result<Self> {
+        let delay = match env::var("INLINE_DEBOUNCE_MS") {
+            Ok(ms) => ms.parse().context("invalid INLINE_DEBOUNCE_MS")?,
+            Err(_) => DEFAULT_DELAY_MS,
+        };
+        Ok(Self {
+            delay: Duration::from_millis(delay),
+            latest: Mutex::default(),
+            next: AtomicU64::new(0),
+        })
+    }
+
+    /// Registers a new query of `user`, superseding the previous ones.
+    pub fn ticket(&self, user: i64) -> Ticket<'_> {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        self.latest.lock().unwrap().insert(user, seq);
+        Ticket {
+            debouncer: self,
+            user,
+            seq,
+        }
+    }
+}
+
+pub struct Ticket<'a> {
+    debouncer: &'a Debouncer,
+    user: i64,
+    seq: u64,
+}
+
+impl Ticket<'_> {
+    fn is_latest(&self) -> bool {
+        self.debouncer.latest.lock().unwrap().get(&self.user) == Some(&self.seq)
+    }
+
+    /// Waits for the user to stop typing, returns `false` if a newer query
+    /// arrived meanwhile.
+    pub async fn settle(&self) -> bool {
+        tokio::time::sleep(self.debouncer.delay).await;
+        self.is_latest()
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        let mut latest = self.debouncer.latest.lock().unwrap();
+        if latest.get(&self.user) == Some(&self.seq) {
+            latest.remove(&self.user);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+use crate::{
+    db::{Db, Library},
+    send_saved_media, Bot,
+};
+
+/// Number of duplicate groups shown by a single `/duplicates`.
+const SHOWN_CLUSTERS: usize = 5;
+
+/// Images shown from one group, only these are deleted on keeping one. The
+/// ids of the others go to the callback data, which must fit in 64 bytes.
+const SHOWN_CLUSTER_IMAGES: usize = 5;
+
+/// Groups the user's images into clusters of near-duplicates, largest first.
+async fn clusters(db: &Db, user: i64, model: &str) -> Result<Vec<Vec<i32>>> {
+    let pairs = db.get_duplicate_pairs(user, model).await?;
+
+    // Union-find over image ids, so images similar through a chain of
+    // others end up in the same cluster.
+    let mut parents: HashMap<i32, i32> = HashMap::new();
+    fn root(parents: &mut HashMap<i32, i32>, id: i32) -> i32 {
+        let parent = *parents.entry(id).or_insert(id);
+        if parent == id {
+            return id;
+        }
+        let root = root(parents, parent);
+        parents.insert(id, root);
+        root
+    }
+    for (a, b) in pairs {
+        let (a, b) = (root(&mut parents, a), root(&mut parents, b));
+        if a != b {
+            parents.insert(a.max(b), a.min(b));
+        }
+    }
+
+    let mut clusters: HashMap<i32, Vec<i32>> = HashMap::new();
+    let ids: Vec<_> = parents.keys().copied().collect();
+    for id in ids {
+        let root = root(&mut parents, id);
+        clusters.entry(root).or_default().push(id);
+    }
+
+    let mut clusters: Vec<_> = clusters.into_values().collect();
+    for cluster in &mut clusters {
+        cluster.sort_unstable();
+    }
+    clusters.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+    Ok(clusters)
+}
+
+/// Shows the largest groups of near-duplicate images, each image with a
+/// button to keep only it.
+pub async fn command(db: &Db, bot: &Bot, msg: &Message, model: &str) -> Result<()> {
+    let clusters = clusters(db, msg.chat.id.0, model).await?;
+    if clusters.is_empty() {
+        bot.send_message(msg.chat.id, "Похожих изображений не найдено.")
+            .await?;
+        return Ok(());
+    }
+
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "Найдено групп похожих изображений: {}. Выберите в каждой группе \
+            изображение, которое нужно оставить, остальные будут перемещены в корзину.",
+            clusters.len()
+        ),
+    )
+    .await?;
+
+    for (i, cluster) in clusters.into_iter().take(SHOWN_CLUSTERS).enumerate() {
+        bot.send_message(
+            msg.chat.id,
+            format!("Группа {}: {} изображений.", i + 1, cluster.len()),
+        )
+        .await?;
+
+        let images = db
+            .get_images(
+                msg.chat.id.0,
+                cluster.into_iter().take(SHOWN_CLUSTER_IMAGES).collect(),
+            )
+            .await?;
+        let ids: Vec<_> = images.iter().map(|image| image.id).collect();
+        for image in images {
+            let others: Vec<_> = ids
+                .iter()
+                .filter(|other| **other != image.id)
+                .map(ToString::to_string)
+                .collect();
+            let keyboard = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+                "Оставить это",
+                format!("dupkeep:{}:{}", image.id, others.join(",")),
+            )]]);
+            send_saved_media(bot, msg.chat.id, image, keyboard).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves the other images shown with the kept one to the trash, returns the
+/// number of moved images.
+pub async fn keep(db: &Db, user: i64, others: &str) -> Result<usize> {
+    let mut deleted = 0;
+    for other in others.split(',').filter(|other| !other.is_empty()) {
+        let other = other.parse()?;
+        if db.delete_image(Library::User(user), other).await? {
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
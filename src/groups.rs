@@ -0,0 +1,121 @@
+use anyhow::Result;
+use teloxide::{prelude::*, types::User, utils::command::BotCommands as _};
+
+use crate::{
+    ai::Ai,
+    db::{Db, Library},
+    delete_command,
+    media::message_media,
+    new_image,
+    translator::Translator,
+    Bot, Command,
+};
+
+/// Handles messages in group chats. Nothing is saved automatically there,
+/// admins curate the group library with `/save` and `/delete` replies and
+/// members pick it for inline search with `/library`.
+pub async fn handle_message(
+    db: &Db,
+    ai: &Ai,
+    translator: &Translator,
+    bot: &Bot,
+    msg: &Message,
+    from: &User,
+) -> Result<()> {
+    let Some(text) = msg.text() else {
+        return Ok(());
+    };
+    let Ok(cmd) = Command::parse(text, bot.get_me().await?.username()) else {
+        return Ok(());
+    };
+    let library = Library::Group(msg.chat.id.0);
+
+    match cmd {
+        Command::Save | Command::Delete => {
+            if !bot
+                .get_chat_member(msg.chat.id, from.id)
+                .await?
+                .is_privileged()
+            {
+                bot.send_message(
+                    msg.chat.id,
+                    "Изменять библиотеку группы могут только администраторы.",
+                )
+                .reply_to_message_id(msg.id)
+                .await?;
+                return Ok(());
+            }
+            db.update_group(msg.chat.id.0).await?;
+
+            if let Command::Delete = cmd {
+                return delete_command(db, bot, msg, library).await;
+            }
+            save(db, ai, translator, bot, msg, library).await
+        }
+        Command::Library => {
+            let user = from.id.0.try_into().unwrap();
+            db.update_user(user).await?;
+            db.update_group(msg.chat.id.0).await?;
+            db.select_group(user, msg.chat.id.0).await?;
+            bot.send_message(
+                msg.chat.id,
+                "Теперь в библиотеке этой группы можно искать так: @picsavbot g: кот",
+            )
+            .reply_to_message_id(msg.id)
+            .await?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Saves the media the command replies to into the group library.
+async fn save(
+    db: &Db,
+    ai: &Ai,
+    translator: &Translator,
+    bot: &Bot,
+    msg: &Message,
+    library: Library,
+) -> Result<()> {
+    let reply = msg.reply_to_message();
+    let Some(media) = reply.and_then(message_media) else {
+        bot.send_message(
+            msg.chat.id,
+            "Чтобы сохранить изображение в библиотеку группы, ответьте на него командой /save.",
+        )
+        .reply_to_message_id(msg.id)
+        .await?;
+        return Ok(());
+    };
+    if media.preview.is_none() {
+        bot.send_message(msg.chat.id, media.no_preview_text())
+            .reply_to_message_id(msg.id)
+            .await?;
+        return Ok(());
+    }
+
+    let text = if db
+        .get_image_id(library, media.file.unique_id.clone())
+        .await?
+        .is_some()
+    {
+        "Это изображение уже есть в библиотеке группы."
+    } else if let Some(id) = db
+        .get_deleted_image_id(library, media.file.unique_id.clone())
+        .await?
+    {
+        db.restore_image(library, id).await?;
+        "Это изображение было удалено из библиотеки группы, теперь оно восстановлено."
+    } else {
+        let caption = reply.and_then(Message::caption).map(ToOwned::to_owned);
+        let image = new_image(bot, db, ai, translator, library, media, caption).await?;
+        db.create_image(image).await?;
+        "Сохранено в библиотеку группы. Чтобы искать в ней, выполните /library и \
+        пишите @picsavbot g: описание"
+    };
+    bot.send_message(msg.chat.id, text)
+        .reply_to_message_id(msg.id)
+        .await?;
+    Ok(())
+}
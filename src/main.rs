@@ -1,35 +1,44 @@
-use std::{
-    collections::{BTreeMap, HashMap},
-    env,
-    future::Future,
-    str::FromStr,
-    sync::{Arc, Mutex},
-};
+use std::{collections::BTreeMap, env, future::Future, str::FromStr, sync::Arc, time::Duration};
 
+use ai::{Ai, TextEncoder};
 use anyhow::{Context, Result};
-use db::Db;
-use reqwest::Client;
+use db::{Cursor, Db, ImageFilter, ImageWithIds, Library, NewImage};
+use debounce::{Debouncer, Ticket};
+use pending::PendingImages;
+use query_cache::QueryCache;
 use sentry::protocol::Value;
-use serde::{Deserialize, Serialize};
 use teloxide::{
     adaptors::{throttle::Limits, Throttle},
     macros::BotCommands,
     net::Download,
     prelude::*,
     types::{
-        InlineQueryResult, InlineQueryResultArticle, InlineQueryResultCachedPhoto,
-        InlineQueryResultCachedSticker, InlineQueryResultCachedVideo, InputMessageContent,
-        InputMessageContentText, ParseMode, User,
+        InlineKeyboardButton, InlineKeyboardMarkup, InlineQueryResult, InlineQueryResultArticle,
+        InlineQueryResultCachedDocument, InlineQueryResultCachedMpeg4Gif,
+        InlineQueryResultCachedPhoto, InlineQueryResultCachedSticker, InlineQueryResultCachedVideo,
+        InputFile, InputMessageContent, InputMessageContentText, ParseMode, User,
     },
     utils::command::BotCommands as _,
 };
-use tokio::sync::Mutex as AsyncMutex;
 use tracing::*;
 use tracing_subscriber::prelude::*;
+use translator::Translator;
 
-use entities::sea_orm_active_enums::MediaType;
+use entities::{collections, sea_orm_active_enums::MediaType};
+use media::{message_media, Media};
 
+mod ai;
 mod db;
+mod debounce;
+mod duplicates;
+mod groups;
+mod media;
+mod pending;
+mod phash;
+mod query;
+mod query_cache;
+mod reindex;
+mod translator;
 
 type Bot = Throttle<teloxide::Bot>;
 
@@ -88,14 +97,37 @@ async fn _main() -> Result<()> {
         .branch(
             Update::filter_chosen_inline_result().branch(dptree::endpoint(handle_chosen_inline)),
         )
-        .branch(Update::filter_inline_query().branch(dptree::endpoint(handle_inline_query)));
+        .branch(Update::filter_inline_query().branch(dptree::endpoint(handle_inline_query)))
+        .branch(Update::filter_callback_query().branch(dptree::endpoint(handle_callback_query)));
 
     let db = Arc::new(Db::new().await?);
-    let ai = Arc::new(Ai::default());
-    let translator = Arc::new(Translator::new()?);
+    let ai = Arc::new(Ai::from_env()?);
+    let translator = Arc::new(Translator::from_env(db.clone())?);
+    let query_cache = Arc::new(QueryCache::from_env(
+        db.clone(),
+        ai.clone(),
+        translator.clone(),
+    )?);
+    let debouncer = Arc::new(Debouncer::from_env()?);
+    let pending = Arc::new(PendingImages::new());
+    tokio::spawn(purge_query_cache(query_cache.clone()));
+    let trash_retention_days = match env::var("TRASH_RETENTION_DAYS") {
+        Ok(days) => days.parse()?,
+        Err(_) => DEFAULT_TRASH_RETENTION_DAYS,
+    };
+
+    reindex::resume(bot.clone(), db.clone(), ai.clone(), translator.clone()).await?;
+    tokio::spawn(purge_trash(db.clone(), trash_retention_days));
 
     Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![db, ai, translator])
+        .dependencies(dptree::deps![
+            db,
+            ai,
+            translator,
+            query_cache,
+            debouncer,
+            pending
+        ])
         .enable_ctrlc_handler()
         // .worker_queue_size(2)
         .build()
@@ -105,175 +137,104 @@ async fn _main() -> Result<()> {
     Ok(())
 }
 
-#[derive(Default)]
-struct Ai {
-    client: Client,
-    lock: AsyncMutex<()>,
-}
-
-#[derive(Deserialize, Debug)]
-struct EmbeddingsResponse {
-    embeddings: Vec<Vec<f32>>,
-}
-
-impl EmbeddingsResponse {
-    fn get_one(self) -> Result<Vec<f32>> {
-        self.embeddings
-            .into_iter()
-            .next()
-            .context("empty embeddings")
-    }
-}
-
-impl Ai {
-    async fn images_embeddings(&self, images: Vec<Vec<u8>>) -> Result<EmbeddingsResponse> {
-        let lock = self.lock.lock().await;
-
-        let mut form = reqwest::multipart::Form::new();
-
-        for image in images {
-            let file_part = reqwest::multipart::Part::bytes(image).file_name("image");
-            form = form.part("files", file_part);
-        }
-
-        let req = self
-            .client
-            .post("http://127.0.0.1:8526/images")
-            .multipart(form)
-            .send()
-            .await?
-            .error_for_status()?;
-        drop(lock);
-
-        Ok(req.json().await?)
-    }
-
-    async fn text_embeddings(&self, texts: Vec<String>) -> Result<EmbeddingsResponse> {
-        #[derive(Serialize, Debug)]
-        struct TextRequest {
-            texts: Vec<String>,
-        }
+const ADMIN_ID: i64 = 1004106925;
 
-        let lock = self.lock.lock().await;
-
-        let res = self
-            .client
-            .post("http://127.0.0.1:8526/texts")
-            .json(&TextRequest { texts })
-            .send()
-            .await?
-            .error_for_status()?;
-        drop(lock);
-
-        Ok(res.json().await?)
-    }
-}
-
-struct Translator {
-    ycl_api_key: String,
-    ycl_folder: String,
-    client: Client,
-    cache: Mutex<HashMap<String, String>>,
-}
-
-impl Translator {
-    fn new() -> Result<Self> {
-        Ok(Self {
-            ycl_api_key: env::var("YCL_API_KEY")?,
-            ycl_folder: env::var("YCL_FOLDER")?,
-            client: Client::new(),
-            cache: Mutex::default(),
-        })
-    }
-
-    async fn translate(&self, text: String) -> Result<String> {
-        #[derive(Serialize)]
-        #[serde(rename_all = "camelCase")]
-        struct TranslateRequest {
-            folder_id: String,
-            texts: [String; 1],
-            target_language_code: String,
-            source_language_code: String,
-            speller: bool,
-        }
-
-        #[derive(Deserialize)]
-        struct TranslateResponse {
-            translations: [Translation; 1],
-        }
-
-        #[derive(Deserialize)]
-        struct Translation {
-            text: String,
-        }
-
-        let cached_translation = self.cache.lock().unwrap().get(&text).cloned();
-        if let Some(translation) = cached_translation {
-            Ok(translation)
-        } else {
-            let res: TranslateResponse = self
-                .client
-                .post("https://translate.api.cloud.yandex.net/translate/v2/translate")
-                .header("Authorization", format!("Api-Key {}", self.ycl_api_key))
-                .json(&TranslateRequest {
-                    folder_id: self.ycl_folder.clone(),
-                    texts: [text.clone()],
-                    target_language_code: "en".into(),
-                    source_language_code: "ru".into(),
-                    speller: true,
-                })
-                .send()
-                .await?
-                .error_for_status()?
-                .json()
-                .await?;
+/// Days deleted images stay in the trash if `TRASH_RETENTION_DAYS` is unset.
+const DEFAULT_TRASH_RETENTION_DAYS: u32 = 30;
 
-            let translation = res.translations.into_iter().map(|t| t.text).next().unwrap();
-            self.cache.lock().unwrap().insert(text, translation.clone());
-            Ok(translation)
-        }
-    }
-}
+/// Number of deleted images shown by `/trash`.
+const TRASH_PAGE_SIZE: u64 = 10;
 
 #[derive(BotCommands)]
 enum Command {
     #[command(rename = "reindex")]
     Reindex,
+    #[command(rename = "delete")]
+    Delete,
+    #[command(rename = "trash")]
+    Trash,
+    #[command(rename = "duplicates")]
+    Duplicates,
+    #[command(rename = "newcollection")]
+    NewCollection(String),
+    #[command(rename = "move")]
+    Move(String),
+    #[command(rename = "share")]
+    Share(String),
+    #[command(rename = "unshare")]
+    Unshare(String),
+    #[command(rename = "subscriptions")]
+    Subscriptions,
+    #[command(rename = "save")]
+    Save,
+    #[command(rename = "library")]
+    Library,
+    #[command(rename = "start")]
+    Start(String),
+    #[command(rename = "langstats")]
+    LangStats,
+    #[command(rename = "textencoder")]
+    TextEncoder(String),
     // User(i64),
 }
 
 async fn handle_inline_query(
     db: Arc<Db>,
     ai: Arc<Ai>,
-    translator: Arc<Translator>,
+    query_cache: Arc<QueryCache>,
+    debouncer: Arc<Debouncer>,
     bot: Bot,
     query: InlineQuery,
 ) -> Result<()> {
     try_handle(&query.from, &bot, async {
-        let offset: Option<u64> = if let Ok(offset) = query.offset.parse() {
-            Some(offset)
+        let cursor = Cursor::decode(&query.offset);
+
+        let user: i64 = query.from.id.0.try_into().unwrap();
+        let ticket = debouncer.ticket(user);
+        // Saves a database query on every keystroke of queries without a
+        // collection prefix.
+        let collections = if query.query.contains(':') {
+            db.get_collection_names(user).await?
         } else {
-            None
+            Vec::new()
         };
-
-        let images: Vec<_> = if query.query.is_empty() {
-            db.get_most_used_images(query.from.id.0.try_into().unwrap(), offset)
-                .await?
+        let parsed = query::parse(&query.query, &collections);
+        let library = if parsed.group {
+            selected_group_library(&db, &bot, &query.from).await?
         } else {
-            let translated_text = translator.translate(query.query).await?;
-
-            let embeddings = ai.text_embeddings(vec![translated_text]).await?;
-            let embedding = embeddings.get_one()?;
+            Some(Library::User(user))
+        };
 
-            db.search_images(query.from.id.0.try_into().unwrap(), embedding, offset)
-                .await?
+        let found = match library {
+            Some(library) => {
+                match search_library(&db, &ai, &query_cache, &ticket, library, parsed, cursor)
+                    .await?
+                {
+                    Some(found) => found,
+                    None => {
+                        debug!("dropping superseded inline query");
+                        return Ok(());
+                    }
+                }
+            }
+            None => Found::default(),
         };
 
-        let images_len = images.len();
-        let results: Vec<_> = images
-            .into_iter()
-            .take(50)
-            .map(|i| match i.media_type {
+        let mut results = Vec::new();
+        if found.nothing_relevant {
+            results.push(InlineQueryResult::Article(
+                InlineQueryResultArticle::new(
+                    "nothingrelevant",
+                    "Ничего подходящего не найдено",
+                    InputMessageContent::Text(InputMessageContentText::new(
+                        bot.get_me().await?.tme_url(),
+                    )),
+                )
+                .description("Показаны самые используемые изображения"),
+            ));
+        }
+        results.extend(found.images.into_iter().take(50 - results.len()).map(|i| {
+            match i.media_type {
                 MediaType::Photo => InlineQueryResult::CachedPhoto(
                     InlineQueryResultCachedPhoto::new(i.id.to_string(), i.file_id),
                 ),
@@ -287,8 +248,19 @@ async fn handle_inline_query(
                         i.id.to_string(),
                     ))
                 }
-            })
-            .collect();
+                // Telegram converts uploaded GIFs to silent MPEG-4 videos.
+                MediaType::Animation => InlineQueryResult::CachedMpeg4Gif(
+                    InlineQueryResultCachedMpeg4Gif::new(i.id.to_string(), i.file_id),
+                ),
+                MediaType::Document => {
+                    InlineQueryResult::CachedDocument(InlineQueryResultCachedDocument::new(
+                        i.id.to_string(),
+                        i.id.to_string(),
+                        i.file_id,
+                    ))
+                }
+            }
+        }));
 
         if results.is_empty() {
             bot.answer_inline_query(
@@ -310,23 +282,107 @@ async fn handle_inline_query(
             .await?;
         } else {
             let mut req = bot.answer_inline_query(query.id, results).cache_time(0);
-            if images_len >= 51 {
-                let new_offset = if let Some(offset) = offset {
-                    offset + 50
-                } else {
-                    50
-                };
-                req = req.next_offset(new_offset.to_string());
+            if let Some(next) = found.next {
+                req = req.next_offset(next.encode());
             }
             req.await?;
         }
 
-        db.update_user(query.from.id.0.try_into().unwrap()).await?;
+        db.update_user(user).await?;
         Ok(())
     })
     .await
 }
 
+/// Returns the user's selected group library if they're still a member.
+async fn selected_group_library(db: &Db, bot: &Bot, user: &User) -> Result<Option<Library>> {
+    let Some(group) = db.get_selected_group(user.id.0.try_into().unwrap()).await? else {
+        return Ok(None);
+    };
+    let member = bot
+        .get_chat_member(ChatId(group), user.id)
+        .await
+        .is_ok_and(|m| m.is_present());
+    Ok(member.then_some(Library::Group(group)))
+}
+
+/// Images answering an inline query.
+#[derive(Default)]
+struct Found {
+    images: Vec<ImageWithIds>,
+    /// Nothing was similar enough to the query, so `images` are the most
+    /// used ones instead.
+    nothing_relevant: bool,
+    next: Option<Cursor>,
+}
+
+/// Returns `None` if a newer query of the user superseded this one before
+/// it was embedded.
+async fn search_library(
+    db: &Db,
+    ai: &Ai,
+    query_cache: &QueryCache,
+    ticket: &Ticket<'_>,
+    library: Library,
+    parsed: query::ParsedQuery,
+    cursor: Option<Cursor>,
+) -> Result<Option<Found>> {
+    if parsed.text.is_empty() && parsed.tags.is_empty() {
+        let filter = ImageFilter {
+            collection: parsed.collection,
+            media_type: parsed.media_type,
+            ..Default::default()
+        };
+        let page = db.get_most_used_images(library, &filter, cursor).await?;
+        return Ok(Some(Found {
+            images: page.images,
+            nothing_relevant: false,
+            next: page.next,
+        }));
+    }
+
+    // Queries of only tags are ranked by similarity to the tags themselves,
+    // but every tagged image matches them.
+    let cutoff = !parsed.text.is_empty();
+    let text = if parsed.text.is_empty() {
+        parsed.tags.join(" ")
+    } else {
+        parsed.text
+    };
+    let filter = ImageFilter {
+        tags: parsed.tags,
+        collection: parsed.collection,
+        media_type: parsed.media_type,
+    };
+
+    let Some(embedding) = query_cache.embed(&text, ticket).await? else {
+        return Ok(None);
+    };
+    let page = db
+        .search_images(library, embedding, ai.model(), &filter, cutoff, cursor)
+        .await?;
+    if let Some(best) = page.images.first() {
+        debug!("best match distance {}", best.distance);
+    }
+
+    if page.images.is_empty() && cursor.is_none() {
+        let images = db
+            .get_most_used_images(library, &filter, None)
+            .await?
+            .images;
+        return Ok(Some(Found {
+            nothing_relevant: !images.is_empty(),
+            images,
+            next: None,
+        }));
+    }
+    Ok(Some(Found {
+        images: page.images.into_iter().map(Into::into).collect(),
+        nothing_relevant: false,
+        next: page.next,
+    }))
+}
+
 async fn handle_chosen_inline(db: Arc<Db>, chosen: ChosenInlineResult) -> Result<()> {
     if let Ok(image) = chosen.result_id.parse() {
         db.increment_image_uses(image, chosen.from.id.0.try_into().unwrap())
@@ -335,88 +391,158 @@ async fn handle_chosen_inline(db: Arc<Db>, chosen: ChosenInlineResult) -> Result
     Ok(())
 }
 
-async fn handle_message(db: Arc<Db>, ai: Arc<Ai>, bot: Bot, msg: Message) -> Result<()> {
+async fn handle_message(
+    db: Arc<Db>,
+    ai: Arc<Ai>,
+    translator: Arc<Translator>,
+    pending: Arc<PendingImages>,
+    bot: Bot,
+    msg: Message,
+) -> Result<()> {
     if let Some(from) = msg.from() {
         try_handle(from, &bot, async {
+            if !msg.chat.is_private() {
+                return groups::handle_message(&db, &ai, &translator, &bot, &msg, from).await;
+            }
             db.update_user(msg.chat.id.0).await?;
+            let library = Library::User(msg.chat.id.0);
 
-            #[allow(clippy::manual_map)]
-            if let Some((media_type, real_file, photo_file)) = if let Some([.., photo]) = msg.photo() {
-                Some((MediaType::Photo, photo.file.clone(), photo.file.clone()))
-            } else if let Some(video) = msg.video() {
-                match &video.thumb {
-                    Some(thumb) => {
-                        Some((MediaType::Video, video.file.clone(), thumb.file.clone()))
-                    },
-                    None => {
-                        bot.send_message(msg.chat.id, "У этого видео нет изображения-предпросмотра.")
-                            .reply_to_message_id(msg.id)
-                            .await?;
-                        return Ok(());
-                    }
-                }
-            } else if let Some(sticker) = msg.sticker() {
-                if sticker.is_raster() {
-                    Some((MediaType::Sticker, sticker.file.clone(), sticker.file.clone()))
-                } else {
-                    match &sticker.thumb {
-                        Some(thumb) => {
-                            Some((MediaType::Sticker, sticker.file.clone(), thumb.file.clone()))
-                        },
-                        None => {
-                            bot.send_message(msg.chat.id, "У этого анимированного стикера нет изображения-предпросмотра.")
-                                .reply_to_message_id(msg.id)
-                                .await?;
-                            return Ok(());
-                        }
-                    }
+            if let Some(media) = message_media(&msg) {
+                if media.preview.is_none() {
+                    bot.send_message(msg.chat.id, media.no_preview_text())
+                        .reply_to_message_id(msg.id)
+                        .await?;
+                    return Ok(());
                 }
-            } else {
-                None
-            } {
-                if db.delete_image(msg.chat.id.0, real_file.unique_id.clone()).await? {
-                    bot.send_message(msg.chat.id, "Изображение удалено!").reply_to_message_id(msg.id).await?;
+                if let Some(id) = db.get_image_id(library, media.file.unique_id.clone()).await? {
+                    bot.send_message(msg.chat.id, "Это изображение уже сохранено. Удалить его?")
+                        .reply_markup(InlineKeyboardMarkup::new([[
+                            InlineKeyboardButton::callback("Удалить", format!("delete:{id}")),
+                            InlineKeyboardButton::callback("Оставить", format!("keep:{id}")),
+                        ]]))
+                        .reply_to_message_id(msg.id)
+                        .await?;
+                } else if let Some(id) = db.get_deleted_image_id(library, media.file.unique_id.clone()).await? {
+                    db.restore_image(library, id).await?;
+                    bot.send_message(msg.chat.id, "Это изображение было в корзине, теперь оно восстановлено.")
+                        .reply_to_message_id(msg.id)
+                        .await?;
                 } else {
-                    let file = bot.get_file(&photo_file.id).await?;
-                    let mut dst = Vec::new();
-                    bot.download_file(&file.path, &mut dst).await?;
+                    let caption = msg.caption().map(ToOwned::to_owned);
+                    let image = new_image(&bot, &db, &ai, &translator, library, media, caption).await?;
 
-                    let embeddings = ai.images_embeddings(vec![dst]).await?;
-                    let embedding = embeddings.get_one()?;
+                    let similar = match image.phash {
+                        Some(phash) => db.find_similar_image(msg.chat.id.0, phash).await?,
+                        None => None,
+                    };
+                    if let Some(similar) = similar {
+                        // Saved only once the user confirms.
+                        pending.insert(msg.chat.id.0, msg.id.0, image);
 
-                    db.create_image(msg.chat.id.0, embedding, real_file.id, real_file.unique_id, media_type)
+                        for existing in db.get_images(msg.chat.id.0, vec![similar]).await? {
+                            send_saved_media(&bot, msg.chat.id, existing, InlineKeyboardMarkup::default()).await?;
+                        }
+                        bot.send_message(
+                            msg.chat.id,
+                            "Похоже, такое изображение у вас уже сохранено. Сохранить и это?",
+                        )
+                        .reply_markup(InlineKeyboardMarkup::new([[
+                            InlineKeyboardButton::callback("Оставить оба", format!("keepboth:{}", msg.id.0)),
+                            InlineKeyboardButton::callback("Не сохранять", format!("skip:{}", msg.id.0)),
+                        ]]))
+                        .reply_to_message_id(msg.id)
                         .await?;
+                        return Ok(());
+                    }
+                    let id = db.create_image(image).await?;
 
                     bot.send_message(
                         msg.chat.id,
                         "Ваше изображение/стикер сохранено\\!\n\nТеперь вы можете найти и \
-                    отправить его, написав `@picsavbot \\[описание изображения по-русски\\]` в любом чате\\.\n\nА чтобы его удалить, \
-                    отправьте его ещё раз с помощью `@picsavbot \\[описание изображения по-русски\\]`\\.",
+                    отправить его, написав `@picsavbot \\[описание изображения по-русски\\]` в любом чате\\.\n\nЧтобы добавить теги, \
+                    ответьте на изображение сообщением вида `#кот #реакция`, а потом ищите по ним: `@picsavbot #кот`\\.\n\nА чтобы его удалить, \
+                    ответьте на него командой /delete или отправьте его ещё раз с помощью `@picsavbot \\[описание изображения по-русски\\]`\\.\n\nЧтобы разложить изображения \
+                    по коллекциям, создайте коллекцию командой `/newcollection название` и ищите в ней: `@picsavbot название: кот`\\.\n\nЧтобы искать только стикеры, \
+                    начните запрос с `s:`: `@picsavbot s: кот` \\(`p:` — фото, `v:` — видео\\)\\.",
                     )
                     .parse_mode(ParseMode::MarkdownV2)
                     .reply_to_message_id(msg.id)
                     .await?;
+
+                    let collections = db.get_collections(msg.chat.id.0).await?;
+                    if !collections.is_empty() {
+                        bot.send_message(msg.chat.id, "Добавить изображение в коллекцию?")
+                            .reply_markup(collections_keyboard(id, &collections, false))
+                            .reply_to_message_id(msg.id)
+                            .await?;
+                    }
                 }
             } else {
-                if msg.chat.id.0 == 1004106925 {
-                    if let Some(text) = msg.text() {
-                        if let Ok(cmd) = Command::parse(text, bot.get_me().await?.username()) {
-                            match cmd {
-                                Command::Reindex => {
-                                    for image in db.get_all_images().await? {
-                                        let file = bot.get_file(image.file_id).await?;
-                                        let mut dst = Vec::new();
-                                        bot.download_file(&file.path, &mut dst).await?;
-
-                                        let embeddings = ai.images_embeddings(vec![dst]).await?;
-                                        let embedding = embeddings.get_one()?;
-
-                                        db.update_image(image.id, embedding).await?;
-                                        info!("reindexed {}", image.id);
-                                    }
-                                }
-                                // Command::User(_) => {},
+                if let (Some(text), Some(reply)) = (msg.text(), msg.reply_to_message()) {
+                    let tags = query::parse_tags(text);
+                    if !tags.is_empty() {
+                        return add_tags(&db, &bot, &msg, reply, tags).await;
+                    }
+                }
+                if let Some(text) = msg.text() {
+                    if let Ok(cmd) = Command::parse(text, bot.get_me().await?.username()) {
+                        match cmd {
+                            Command::Delete => {
+                                return delete_command(&db, &bot, &msg, library).await;
+                            }
+                            Command::Trash => {
+                                return trash_command(&db, &bot, &msg).await;
+                            }
+                            Command::NewCollection(name) => {
+                                return new_collection_command(&db, &bot, &msg, &name).await;
+                            }
+                            Command::Move(name) => {
+                                return move_command(&db, &bot, &msg, &name).await;
+                            }
+                            Command::Share(name) => {
+                                return share_command(&db, &bot, &msg, &name).await;
+                            }
+                            Command::Unshare(name) => {
+                                return unshare_command(&db, &bot, &msg, &name).await;
+                            }
+                            Command::Subscriptions => {
+                                return subscriptions_command(&db, &bot, &msg).await;
+                            }
+                            Command::Start(payload) if payload.starts_with("share_") => {
+                                return subscribe(&db, &bot, &msg, &payload["share_".len()..]).await;
                             }
+                            Command::Duplicates => {
+                                return duplicates::command(&db, &bot, &msg, ai.model()).await;
+                            }
+                            Command::Reindex if msg.chat.id.0 == ADMIN_ID => {
+                                reindex::start(bot.clone(), db.clone(), ai.clone(), translator.clone(), msg.chat.id)
+                                    .await?;
+                                return Ok(());
+                            }
+                            Command::LangStats if msg.chat.id.0 == ADMIN_ID => {
+                                let stats = translator.language_stats();
+                                let text = if stats.is_empty() {
+                                    String::from("Запросов ещё не было.")
+                                } else {
+                                    stats
+                                        .iter()
+                                        .map(|(lang, count)| format!("{lang}: {count}"))
+                                        .collect::<Vec<_>>()
+                                        .join("\n")
+                                };
+                                bot.send_message(msg.chat.id, text).await?;
+                                return Ok(());
+                            }
+                            Command::TextEncoder(encoder) if msg.chat.id.0 == ADMIN_ID => {
+                                return text_encoder_command(&ai, &bot, &msg, &encoder).await;
+                            }
+                            Command::Reindex
+                            | Command::LangStats
+                            | Command::TextEncoder(_)
+                            | Command::Start(_)
+                            | Command::Save
+                            | Command::Library => {}
+                            // Command::User(_) => {},
                         }
                     }
                 }
@@ -435,6 +561,575 @@ async fn handle_message(db: Arc<Db>, ai: Arc<Ai>, bot: Bot, msg: Message) -> Res
     }
 }
 
+/// Deletes the saved media the command replies to.
+async fn delete_command(db: &Db, bot: &Bot, msg: &Message, library: Library) -> Result<()> {
+    let image = match msg.reply_to_message().and_then(message_media) {
+        Some(media) => db.get_image_id(library, media.file.unique_id).await?,
+        None => None,
+    };
+
+    match image {
+        Some(image) if db.delete_image(library, image).await? => {
+            bot.send_message(msg.chat.id, "Изображение перемещено в корзину.")
+                .reply_markup(restore_keyboard(image, "Отменить"))
+                .reply_to_message_id(msg.id)
+                .await?;
+        }
+        _ => {
+            bot.send_message(
+                msg.chat.id,
+                "Чтобы удалить изображение, ответьте на него командой /delete.",
+            )
+            .reply_to_message_id(msg.id)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn new_collection_command(db: &Db, bot: &Bot, msg: &Message, name: &str) -> Result<()> {
+    let Some(name) = query::parse_collection_name(name) else {
+        bot.send_message(
+            msg.chat.id,
+            "Использование: /newcollection название. Название должно быть одним словом \
+            и не может быть g, s, p, v, a или d.",
+        )
+        .reply_to_message_id(msg.id)
+        .await?;
+        return Ok(());
+    };
+
+    let text = if db.create_collection(msg.chat.id.0, name.clone()).await? {
+        format!(
+            "Коллекция «{name}» создана. Чтобы переместить в неё изображение, \
+            ответьте на него командой /move {name}, а искать в ней можно так: @picsavbot {name}: кот"
+        )
+    } else {
+        format!("Коллекция «{name}» уже есть.")
+    };
+    bot.send_message(msg.chat.id, text)
+        .reply_to_message_id(msg.id)
+        .await?;
+    Ok(())
+}
+
+/// Moves the replied image to the named collection, or offers to choose one
+/// if no name is given.
+async fn move_command(db: &Db, bot: &Bot, msg: &Message, name: &str) -> Result<()> {
+    let image = match msg.reply_to_message().and_then(message_media) {
+        Some(media) => {
+            db.get_image_id(Library::User(msg.chat.id.0), media.file.unique_id)
+                .await?
+        }
+        None => None,
+    };
+    let Some(image) = image else {
+        bot.send_message(
+            msg.chat.id,
+            "Чтобы переместить изображение в коллекцию, ответьте на него командой /move название.",
+        )
+        .reply_to_message_id(msg.id)
+        .await?;
+        return Ok(());
+    };
+
+    if name.trim().is_empty() {
+        let collections = db.get_collections(msg.chat.id.0).await?;
+        if collections.is_empty() {
+            bot.send_message(
+                msg.chat.id,
+                "У вас пока нет коллекций, создайте её командой /newcollection название.",
+            )
+            .reply_to_message_id(msg.id)
+            .await?;
+        } else {
+            bot.send_message(msg.chat.id, "Выберите коллекцию:")
+                .reply_markup(collections_keyboard(image, &collections, true))
+                .reply_to_message_id(msg.id)
+                .await?;
+        }
+        return Ok(());
+    }
+
+    let collection = match query::parse_collection_name(name) {
+        Some(name) => db.get_collection(msg.chat.id.0, name).await?,
+        None => None,
+    };
+    let text = match collection {
+        Some(collection)
+            if db
+                .move_image(msg.chat.id.0, image, Some(collection.id))
+                .await? =>
+        {
+            format!("Изображение перемещено в коллекцию «{}».", collection.name)
+        }
+        _ => String::from("Такой коллекции нет, создайте её командой /newcollection название."),
+    };
+    bot.send_message(msg.chat.id, text)
+        .reply_to_message_id(msg.id)
+        .await?;
+    Ok(())
+}
+
+/// Sends a link subscribing other users to the collection.
+async fn share_command(db: &Db, bot: &Bot, msg: &Message, name: &str) -> Result<()> {
+    let token = match query::parse_collection_name(name) {
+        Some(name) => db.share_collection(msg.chat.id.0, name).await?,
+        None => None,
+    };
+    let text = match token {
+        Some(token) => format!(
+            "Поделитесь этой ссылкой, чтобы другие могли искать изображения из коллекции: \
+            {}?start=share_{token}\n\nЧтобы закрыть доступ, используйте /unshare {}",
+            bot.get_me().await?.tme_url(),
+            name.trim().to_lowercase()
+        ),
+        None => String::from("Использование: /share название коллекции."),
+    };
+    bot.send_message(msg.chat.id, text)
+        .reply_to_message_id(msg.id)
+        .await?;
+    Ok(())
+}
+
+async fn unshare_command(db: &Db, bot: &Bot, msg: &Message, name: &str) -> Result<()> {
+    let unshared = match query::parse_collection_name(name) {
+        Some(name) => db.unshare_collection(msg.chat.id.0, name).await?,
+        None => false,
+    };
+    let text = if unshared {
+        "Доступ к коллекции закрыт, старая ссылка больше не работает."
+    } else {
+        "Использование: /unshare название коллекции, которой вы поделились."
+    };
+    bot.send_message(msg.chat.id, text)
+        .reply_to_message_id(msg.id)
+        .await?;
+    Ok(())
+}
+
+/// Lists collections the user is subscribed to with unsubscribe buttons.
+async fn subscriptions_command(db: &Db, bot: &Bot, msg: &Message) -> Result<()> {
+    let collections = db.get_subscriptions(msg.chat.id.0).await?;
+    if collections.is_empty() {
+        bot.send_message(msg.chat.id, "Вы не подписаны ни на одну коллекцию.")
+            .await?;
+        return Ok(());
+    }
+
+    let rows: Vec<_> = collections
+        .iter()
+        .map(|c| {
+            vec![InlineKeyboardButton::callback(
+                format!("Отписаться от «{}»", c.name),
+                format!("unsubscribe:{}", c.id),
+            )]
+        })
+        .collect();
+    bot.send_message(
+        msg.chat.id,
+        "Изображения из этих коллекций находятся в вашем поиске:",
+    )
+    .reply_markup(InlineKeyboardMarkup::new(rows))
+    .await?;
+    Ok(())
+}
+
+/// Handles share links opened as `/start share_<token>`.
+async fn subscribe(db: &Db, bot: &Bot, msg: &Message, token: &str) -> Result<()> {
+    let text = match db.subscribe(msg.chat.id.0, token.to_owned()).await? {
+        Some(collection) if collection.user_id == msg.chat.id.0 => {
+            String::from("Это ваша собственная коллекция.")
+        }
+        Some(collection) => format!(
+            "Вы подписались на коллекцию «{0}». Её изображения теперь находятся в вашем \
+            поиске, в том числе так: @picsavbot {0}: кот",
+            collection.name
+        ),
+        None => String::from("Ссылка недействительна: доступ к коллекции закрыт."),
+    };
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+
+/// Buttons moving the image to one of the collections, and optionally out
+/// of any collection.
+fn collections_keyboard(
+    image: i32,
+    collections: &[collections::Model],
+    with_none: bool,
+) -> InlineKeyboardMarkup {
+    let mut rows: Vec<_> = collections
+        .iter()
+        .map(|c| {
+            vec![InlineKeyboardButton::callback(
+                c.name.clone(),
+                format!("move:{image}:{}", c.id),
+            )]
+        })
+        .collect();
+    if with_none {
+        rows.push(vec![InlineKeyboardButton::callback(
+            "Без коллекции",
+            format!("move:{image}:0"),
+        )]);
+    }
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Sends the most recently deleted images with restore buttons.
+async fn trash_command(db: &Db, bot: &Bot, msg: &Message) -> Result<()> {
+    let images = db
+        .get_deleted_images(msg.chat.id.0, TRASH_PAGE_SIZE)
+        .await?;
+    if images.is_empty() {
+        bot.send_message(msg.chat.id, "Корзина пуста.").await?;
+        return Ok(());
+    }
+
+    for image in images {
+        let keyboard = restore_keyboard(image.id, "Восстановить");
+        send_saved_media(bot, msg.chat.id, image, keyboard).await?;
+    }
+    Ok(())
+}
+
+fn restore_keyboard(id: i32, text: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        text,
+        format!("restore:{id}"),
+    )]])
+}
+
+async fn send_saved_media(
+    bot: &Bot,
+    chat: ChatId,
+    image: ImageWithIds,
+    keyboard: InlineKeyboardMarkup,
+) -> Result<()> {
+    let file = InputFile::file_id(image.file_id);
+    match image.media_type {
+        MediaType::Animation => {
+            bot.send_animation(chat, file)
+                .reply_markup(keyboard)
+                .await?;
+        }
+        MediaType::Document => {
+            bot.send_document(chat, file).reply_markup(keyboard).await?;
+        }
+        MediaType::Photo => {
+            bot.send_photo(chat, file).reply_markup(keyboard).await?;
+        }
+        MediaType::Sticker => {
+            bot.send_sticker(chat, file).reply_markup(keyboard).await?;
+        }
+        MediaType::Video => {
+            bot.send_video(chat, file).reply_markup(keyboard).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_callback_query(
+    db: Arc<Db>,
+    pending: Arc<PendingImages>,
+    bot: Bot,
+    q: CallbackQuery,
+) -> Result<()> {
+    try_handle(&q.from, &bot, async {
+        let user: i64 = q.from.id.0.try_into().unwrap();
+        let Some((action, args)) = q.data.as_deref().and_then(|d| d.split_once(':')) else {
+            return Ok(());
+        };
+        let (id, arg) = match args.split_once(':') {
+            Some((id, arg)) => (id, Some(arg)),
+            None => (args, None),
+        };
+        let id: i32 = id.parse()?;
+
+        // Buttons in groups manage the group library and are for admins only.
+        let library = match &q.message {
+            Some(msg) if !msg.chat.is_private() => {
+                if !bot
+                    .get_chat_member(msg.chat.id, q.from.id)
+                    .await?
+                    .is_privileged()
+                {
+                    bot.answer_callback_query(q.id)
+                        .text("Это могут сделать только администраторы группы.")
+                        .await?;
+                    return Ok(());
+                }
+                Library::Group(msg.chat.id.0)
+            }
+            _ => Library::User(user),
+        };
+
+        let (text, keyboard) = match action {
+            "delete" => {
+                if db.delete_image(library, id).await? {
+                    (
+                        "Изображение перемещено в корзину.",
+                        Some(restore_keyboard(id, "Отменить")),
+                    )
+                } else {
+                    ("Изображение уже удалено.", None)
+                }
+            }
+            "keep" => ("Изображение оставлено.", None),
+            "dupkeep" => {
+                let others = arg.context("no images in callback")?;
+                if duplicates::keep(&db, user, others).await? > 0 {
+                    ("Остальные похожие изображения перемещены в корзину.", None)
+                } else {
+                    ("Похожих изображений уже не осталось.", None)
+                }
+            }
+            "move" => {
+                let collection: i32 = arg.context("no collection in callback")?.parse()?;
+                let collection = (collection != 0).then_some(collection);
+                match db.move_image(user, id, collection).await? {
+                    true if collection.is_some() => ("Изображение перемещено в коллекцию.", None),
+                    true => ("Изображение убрано из коллекции.", None),
+                    false => ("Изображение или коллекция уже удалены.", None),
+                }
+            }
+            "unsubscribe" => {
+                if db.unsubscribe(user, id).await? {
+                    ("Вы отписались от коллекции.", None)
+                } else {
+                    ("Вы уже отписались от этой коллекции.", None)
+                }
+            }
+            // Here `id` is the message with the image waiting to be saved.
+            "keepboth" => match pending.take(user, id) {
+                Some(image)
+                    if db
+                        .get_image_id(library, image.unique_id.clone())
+                        .await?
+                        .is_some() =>
+                {
+                    ("Это изображение уже сохранено.", None)
+                }
+                Some(image) => {
+                    db.create_image(image).await?;
+                    ("Оба изображения сохранены.", None)
+                }
+                None => ("Отправьте изображение ещё раз, чтобы сохранить его.", None),
+            },
+            "skip" => {
+                pending.take(user, id);
+                ("Изображение не сохранено.", None)
+            }
+            "restore" => {
+                if db.restore_image(library, id).await? {
+                    ("Изображение восстановлено.", None)
+                } else {
+                    ("Изображение уже нельзя восстановить.", None)
+                }
+            }
+            _ => return Ok(()),
+        };
+
+        // Media messages from /trash have no text to replace, so the result
+        // is shown as a notification instead.
+        match q.message {
+            Some(msg) if msg.text().is_some() => {
+                bot.answer_callback_query(q.id).await?;
+                let mut req = bot.edit_message_text(msg.chat.id, msg.id, text);
+                if let Some(keyboard) = keyboard {
+                    req = req.reply_markup(keyboard);
+                }
+                req.await?;
+            }
+            Some(msg) => {
+                bot.answer_callback_query(q.id).text(text).await?;
+                bot.edit_message_reply_markup(msg.chat.id, msg.id).await?;
+            }
+            None => {
+                bot.answer_callback_query(q.id).text(text).await?;
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Permanently deletes images that stayed in the trash for too long.
+async fn purge_trash(db: Arc<Db>, days: u32) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match db.purge_deleted_images(days).await {
+            Ok(0) => {}
+            Ok(purged) => info!("purged {purged} images from trash"),
+            Err(e) => {
+                error!("can't purge trash: {e:#}");
+                sentry_anyhow::capture_anyhow(&e);
+            }
+        }
+    }
+}
+
+/// Deletes expired query embeddings from the database.
+async fn purge_query_cache(query_cache: Arc<QueryCache>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match query_cache.purge().await {
+            Ok(0) => {}
+            Ok(purged) => info!("purged {purged} cached query embeddings"),
+            Err(e) => {
+                error!("can't purge query cache: {e:#}");
+                sentry_anyhow::capture_anyhow(&e);
+            }
+        }
+    }
+}
+
+async fn add_tags(
+    db: &Db,
+    bot: &Bot,
+    msg: &Message,
+    reply: &Message,
+    tags: Vec<String>,
+) -> Result<()> {
+    let image = match message_media(reply) {
+        Some(media) => {
+            db.get_image_id(Library::User(msg.chat.id.0), media.file.unique_id)
+                .await?
+        }
+        None => None,
+    };
+
+    let text = if let Some(image) = image {
+        let text = format!(
+            "Теги добавлены: {}",
+            tags.iter()
+                .map(|t| format!("#{t}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        db.add_tags(image, tags).await?;
+        text
+    } else {
+        "Чтобы добавить теги, ответьте ими на сохранённое изображение или стикер.".to_owned()
+    };
+    bot.send_message(msg.chat.id, text)
+        .reply_to_message_id(msg.id)
+        .await?;
+    Ok(())
+}
+
+/// Shows the text encoder in use or switches it to `clip` or `multilingual`.
+async fn text_encoder_command(ai: &Ai, bot: &Bot, msg: &Message, encoder: &str) -> Result<()> {
+    let encoder = match encoder.trim() {
+        "" => None,
+        "clip" => Some(TextEncoder::Clip),
+        "multilingual" => Some(TextEncoder::Multilingual),
+        _ => {
+            bot.send_message(
+                msg.chat.id,
+                "Использование: /textencoder [clip|multilingual]",
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let text = match encoder {
+        Some(encoder) => match ai.set_text_encoder(encoder) {
+            Ok(()) => format!("Текстовый энкодер переключён на {encoder:?}."),
+            Err(e) => format!("Не удалось переключить энкодер: {e}"),
+        },
+        None => format!("Текстовый энкодер: {:?}.", ai.text_encoder()),
+    };
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+
+/// Downloads and embeds the media to save it to the library.
+/// Embeds the media from its preview, which the caller checks exists.
+async fn new_image(
+    bot: &Bot,
+    db: &Db,
+    ai: &Ai,
+    translator: &Translator,
+    library: Library,
+    media: Media,
+    caption: Option<String>,
+) -> Result<NewImage> {
+    let preview = media.preview.clone().context("media has no preview")?;
+    let dst = download_file(bot, &preview.id).await?;
+    let phash = match phash::dhash_async(dst.clone()).await {
+        Ok(phash) => Some(phash),
+        Err(e) => {
+            warn!("can't compute perceptual hash: {e:#}");
+            None
+        }
+    };
+    let embedding = ai.image_embedding(dst).await?;
+
+    let caption_embedding = match &caption {
+        Some(caption) => embed_caption(db, ai, translator, caption).await,
+        None => None,
+    };
+
+    Ok(NewImage {
+        library,
+        file_id: media.file.id,
+        preview_file_id: preview.id,
+        unique_id: media.file.unique_id,
+        media_type: media.media_type,
+        embedding,
+        model: ai.model().to_owned(),
+        caption,
+        caption_embedding,
+        phash,
+    })
+}
+
+/// Embeds the caption if captions are ranked at all. Captions always go
+/// through the CLIP text tower, so switching `/textencoder` doesn't mix
+/// vectors of two towers in the ranking. An image is still worth saving
+/// without it, so failures are only logged.
+async fn embed_caption(
+    db: &Db,
+    ai: &Ai,
+    translator: &Translator,
+    caption: &str,
+) -> Option<Vec<f32>> {
+    if !db.captions_enabled() {
+        return None;
+    }
+    embed_text(ai, translator, TextEncoder::Clip, caption.to_owned())
+        .await
+        .map_err(|e| warn!("can't embed caption: {e:#}"))
+        .ok()
+}
+
+/// Embeds a query or a caption with `encoder`, translating it to English
+/// for the CLIP text tower.
+async fn embed_text(
+    ai: &Ai,
+    translator: &Translator,
+    encoder: TextEncoder,
+    text: String,
+) -> Result<Vec<f32>> {
+    let text = match encoder {
+        TextEncoder::Clip => translator.translate(text).await?,
+        TextEncoder::Multilingual => text,
+    };
+    ai.text_embeddings(encoder, vec![text]).await?.get_one()
+}
+
+async fn download_file(bot: &Bot, file_id: &str) -> Result<Vec<u8>> {
+    let file = bot.get_file(file_id).await?;
+    let mut dst = Vec::new();
+    bot.download_file(&file.path, &mut dst).await?;
+    Ok(dst)
+}
+
 async fn try_handle(
     user: &User,
     bot: &Bot,
@@ -0,0 +1,96 @@
+use entities::sea_orm_active_enums::MediaType;
+use teloxide::types::{Document, FileMeta, Message};
+
+/// Media of a message that can be saved.
+pub struct Media {
+    pub media_type: MediaType,
+    /// File sent back in inline results.
+    pub file: FileMeta,
+    /// Image the embedding is computed from, `None` if the media has no
+    /// preview.
+    pub preview: Option<FileMeta>,
+}
+
+impl Media {
+    /// Reply for media without a preview.
+    pub fn no_preview_text(&self) -> &'static str {
+        match self.media_type {
+            MediaType::Video => "У этого видео нет изображения-предпросмотра.",
+            MediaType::Animation => "У этой GIF-анимации нет изображения-предпросмотра.",
+            MediaType::Document => {
+                "Это изображение больше 20 МБ или в неподдерживаемом формате, \
+                а предпросмотра у файла нет. Отправьте его в JPEG, PNG или WebP."
+            }
+            _ => "У этого анимированного стикера нет изображения-предпросмотра.",
+        }
+    }
+}
+
+pub fn message_media(msg: &Message) -> Option<Media> {
+    if let Some([.., photo]) = msg.photo() {
+        Some(Media {
+            media_type: MediaType::Photo,
+            file: photo.file.clone(),
+            preview: Some(photo.file.clone()),
+        })
+    } else if let Some(video) = msg.video() {
+        Some(Media {
+            media_type: MediaType::Video,
+            file: video.file.clone(),
+            preview: video.thumb.as_ref().map(|t| t.file.clone()),
+        })
+    } else if let Some(animation) = msg.animation() {
+        Some(Media {
+            media_type: MediaType::Animation,
+            file: animation.file.clone(),
+            preview: animation.thumb.as_ref().map(|t| t.file.clone()),
+        })
+    } else if let Some(document) = msg.document().filter(|d| is_image_document(d)) {
+        // Uncompressed images are embedded from the original file when the bot
+        // is able to download and decode it, otherwise from the thumbnail.
+        let preview = if document.file.size <= MAX_DOWNLOAD_SIZE && is_decodable(document) {
+            Some(document.file.clone())
+        } else {
+            document.thumb.as_ref().map(|t| t.file.clone())
+        };
+        Some(Media {
+            media_type: MediaType::Document,
+            file: document.file.clone(),
+            preview,
+        })
+    } else if let Some(sticker) = msg.sticker() {
+        let preview = if sticker.is_raster() {
+            Some(sticker.file.clone())
+        } else {
+            sticker.thumb.as_ref().map(|t| t.file.clone())
+        };
+        Some(Media {
+            media_type: MediaType::Sticker,
+            file: sticker.file.clone(),
+            preview,
+        })
+    } else {
+        None
+    }
+}
+
+/// Largest file the Bot API lets bots download.
+const MAX_DOWNLOAD_SIZE: u32 = 20 * 1024 * 1024;
+
+/// Raster images sent as files.
+fn is_image_document(document: &Document) -> bool {
+    document
+        .mime_type
+        .as_ref()
+        .is_some_and(|m| m.type_() == "image" && m.subtype() != "svg")
+}
+
+/// Image formats the model server is able to decode.
+fn is_decodable(document: &Document) -> bool {
+    document.mime_type.as_ref().is_some_and(|m| {
+        matches!(
+            m.subtype().as_str(),
+            "jpeg" | "png" | "webp" | "gif" | "bmp"
+        )
+    })
+}
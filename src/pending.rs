@@ -0,0 +1,33 @@
+use std::{num::NonZeroUsize, sync::Mutex};
+
+use lru::LruCache;
+
+use crate::db::NewImage;
+
+/// Near-duplicates waiting for the user to decide whether to save them,
+/// oldest ones are forgotten first.
+const CAPACITY: usize = 1_000;
+
+/// Images not saved until the user confirms, keyed by the user and the id of
+/// the message with the image.
+pub struct PendingImages {
+    images: Mutex<LruCache<(i64, i32), NewImage>>,
+}
+
+impl PendingImages {
+    pub fn new() -> Self {
+        Self {
+            images: Mutex::new(LruCache::new(NonZeroUsize::new(CAPACITY).unwrap())),
+        }
+    }
+
+    pub fn insert(&self, user: i64, message: i32, image: NewImage) {
+        self.images.lock().unwrap().put((user, message), image);
+    }
+
+    /// Returns `None` if the user already decided or the image was
+    /// forgotten.
+    pub fn take(&self, user: i64, message: i32) -> Option<NewImage> {
+        self.images.lock().unwrap().pop(&(user, message))
+    }
+}
@@ -0,0 +1,27 @@
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+
+/// Computes the 64-bit difference hash of an image: it's scaled down to 9x8
+/// grayscale pixels and every bit tells whether a pixel is brighter than its
+/// right neighbour. Recompressed or resized copies get hashes differing only
+/// in a few bits.
+pub fn dhash(data: &[u8]) -> Result<i64> {
+    let image = image::load_from_memory(data)
+        .context("can't decode image")?
+        .resize_exact(9, 8, FilterType::Triangle)
+        .into_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = image.get_pixel(x, y)[0] > image.get_pixel(x + 1, y)[0];
+            hash = hash << 1 | u64::from(bit);
+        }
+    }
+    Ok(hash as i64)
+}
+
+/// Computes [`dhash`] on the blocking thread pool.
+pub async fn dhash_async(data: Vec<u8>) -> Result<i64> {
+    tokio::task::spawn_blocking(move || dhash(&data)).await?
+}
@@ -0,0 +1,177 @@
+use entities::sea_orm_active_enums::MediaType;
+
+/// Inline query split into filters and the text to search for.
+pub struct ParsedQuery {
+    pub text: String,
+    pub tags: Vec<String>,
+    pub collection: Option<String>,
+    pub media_type: Option<MediaType>,
+    /// Search the selected group library instead of the personal one.
+    pub group: bool,
+}
+
+/// Parses queries like `work: #cat #reaction sleeping`, a leading `name:`
+/// restricts the search to a collection if it's one of `collections`, and
+/// leading hashtags become exact tag filters. A leading `g:` switches to the
+/// group library and `s:`, `p:`, `v:`, `a:` or `d:` keep only stickers,
+/// photos, videos, animations or documents, these go before the collection
+/// name in any order.
+pub fn parse(query: &str, collections: &[String]) -> ParsedQuery {
+    let mut group = false;
+    let mut media_type = None;
+    let mut query = query.trim_start();
+    loop {
+        if let Some(rest) = query.strip_prefix("g:").filter(|_| !group) {
+            group = true;
+            query = rest.trim_start();
+        } else if let Some((t, rest)) = parse_media_prefix(query).filter(|_| media_type.is_none()) {
+            media_type = Some(t);
+            query = rest.trim_start();
+        } else {
+            break;
+        }
+    }
+    let (collection, query) = match query.split_once(':') {
+        Some((name, rest)) => match parse_collection_name(name) {
+            Some(name) if collections.contains(&name) => (Some(name), rest),
+            _ => (None, query),
+        },
+        None => (None, query),
+    };
+
+    let mut tags = Vec::new();
+    let mut words = query.split_whitespace().peekable();
+    while let Some(tag) = words.peek().and_then(|w| parse_tag(w)) {
+        tags.push(tag);
+        words.next();
+    }
+
+    ParsedQuery {
+        text: words.collect::<Vec<_>>().join(" "),
+        tags,
+        collection,
+        media_type,
+        group,
+    }
+}
+
+fn parse_media_prefix(query: &str) -> Option<(MediaType, &str)> {
+    let (prefix, rest) = query.split_once(':')?;
+    Some((media_type(prefix)?, rest))
+}
+
+fn media_type(prefix: &str) -> Option<MediaType> {
+    Some(match prefix {
+        "s" => MediaType::Sticker,
+        "p" => MediaType::Photo,
+        "v" => MediaType::Video,
+        "a" => MediaType::Animation,
+        "d" => MediaType::Document,
+        _ => return None,
+    })
+}
+
+/// Normalizes a collection name, which must be a single word of letters,
+/// digits, `_` or `-` other than the `g:` and media type prefixes.
+pub fn parse_collection_name(name: &str) -> Option<String> {
+    let name = name.trim();
+    let valid = !name.is_empty()
+        && name.chars().count() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    let name = name.to_lowercase();
+    let reserved = name == "g" || media_type(&name).is_some();
+    (valid && !reserved).then_some(name)
+}
+
+/// Returns all hashtags in the text, normalized.
+pub fn parse_tags(text: &str) -> Vec<String> {
+    let mut tags: Vec<_> = text.split_whitespace().filter_map(parse_tag).collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+fn parse_tag(word: &str) -> Option<String> {
+    let tag: String = word
+        .strip_prefix('#')?
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .flat_map(char::to_lowercase)
+        .collect();
+    (!tag.is_empty()).then_some(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_with_collections(query: &str) -> ParsedQuery {
+        parse(query, &[String::from("work"), String::from("s")])
+    }
+
+    #[test]
+    fn plain_query_is_text() {
+        let parsed = parse_with_collections("sleeping cat");
+        assert_eq!(parsed.text, "sleeping cat");
+        assert!(parsed.tags.is_empty());
+        assert_eq!(parsed.collection, None);
+        assert_eq!(parsed.media_type, None);
+        assert!(!parsed.group);
+    }
+
+    #[test]
+    fn prefixes_in_order() {
+        let parsed = parse_with_collections("g:s:work: #Cat #reaction sleeping #not_tag");
+        assert!(parsed.group);
+        assert_eq!(parsed.media_type, Some(MediaType::Sticker));
+        assert_eq!(parsed.collection.as_deref(), Some("work"));
+        assert_eq!(parsed.tags, ["cat", "reaction"]);
+        assert_eq!(parsed.text, "sleeping #not_tag");
+    }
+
+    #[test]
+    fn group_and_media_prefixes_in_any_order() {
+        let parsed = parse_with_collections(" p: g: cat");
+        assert!(parsed.group);
+        assert_eq!(parsed.media_type, Some(MediaType::Photo));
+        assert_eq!(parsed.text, "cat");
+    }
+
+    #[test]
+    fn media_prefix_wins_over_collection() {
+        let parsed = parse_with_collections("s: cat");
+        assert_eq!(parsed.media_type, Some(MediaType::Sticker));
+        assert_eq!(parsed.collection, None);
+        assert_eq!(parsed.text, "cat");
+    }
+
+    #[test]
+    fn unknown_prefix_stays_text() {
+        for query in ["lol: cat", "12:30", "https://example.com"] {
+            let parsed = parse_with_collections(query);
+            assert_eq!(parsed.collection, None);
+            assert_eq!(parsed.text, query);
+        }
+    }
+
+    #[test]
+    fn collection_names() {
+        assert_eq!(parse_collection_name(" Work "), Some(String::from("work")));
+        assert_eq!(
+            parse_collection_name("мемы-2"),
+            Some(String::from("мемы-2"))
+        );
+        assert_eq!(parse_collection_name("two words"), None);
+        assert_eq!(parse_collection_name(""), None);
+        for reserved in ["g", "s", "P", "v", "a", "d"] {
+            assert_eq!(parse_collection_name(reserved), None);
+        }
+    }
+
+    #[test]
+    fn tags_are_normalized() {
+        assert_eq!(parse_tags("#Cat, #dog #cat text #"), ["cat", "dog"]);
+    }
+}
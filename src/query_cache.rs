@@ -0,0 +1,144 @@
+use std::{
+    env,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use lru::LruCache;
+
+use crate::{
+    ai::{Ai, TextEncoder},
+    db::Db,
+    debounce::Ticket,
+    embed_text,
+    translator::Translator,
+};
+
+/// Query embeddings kept in memory if `QUERY_CACHE_SIZE` is unset.
+const DEFAULT_CACHE_SIZE: usize = 2_000;
+
+/// Days persisted embeddings are used if `QUERY_CACHE_TTL_DAYS` is unset.
+const DEFAULT_TTL_DAYS: u32 = 30;
+
+/// Text model, translation provider and normalized query.
+type Key = (String, &'static str, String);
+
+/// Makes queries differing only in case and whitespace share an embedding.
+pub fn normalize(query: &str) -> String {
+    query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Embeddings of recent inline queries, so retyping a query doesn't reach
+/// the translator and the model server again.
+pub struct QueryCache {
+    db: Arc<Db>,
+    ai: Arc<Ai>,
+    translator: Arc<Translator>,
+    cache: Mutex<LruCache<Key, Vec<f32>>>,
+    /// Whether embeddings are also saved to the database, so they survive
+    /// restarts.
+    persist: bool,
+    /// Persisted embeddings older than this are re-embedded and purged.
+    ttl_days: u32,
+}
+
+impl QueryCache {
+    /// Reads the cache size from `QUERY_CACHE_SIZE`, embeddings are
+    /// persisted for `QUERY_CACHE_TTL_DAYS` if `QUERY_CACHE_PERSIST` is
+    /// `true`.
+    pub fn from_env(db: Arc<Db>, ai: Arc<Ai>, translator: Arc<Translator>) -> Result<Self> {
+        let cache_size = match env::var("QUERY_CACHE_SIZE") {
+            Ok(size) => size.parse().context("invalid QUERY_CACHE_SIZE")?,
+            Err(_) => DEFAULT_CACHE_SIZE,
+        };
+        let persist = match env::var("QUERY_CACHE_PERSIST") {
+            Ok(persist) => persist.parse().context("invalid QUERY_CACHE_PERSIST")?,
+            Err(_) => false,
+        };
+        let ttl_days = match env::var("QUERY_CACHE_TTL_DAYS") {
+            Ok(days) => days.parse().context("invalid QUERY_CACHE_TTL_DAYS")?,
+            Err(_) => DEFAULT_TTL_DAYS,
+        };
+
+        Ok(Self {
+            db,
+            ai,
+            translator,
+            cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(cache_size).context("QUERY_CACHE_SIZE must be positive")?,
+            )),
+            persist,
+            ttl_days,
+        })
+    }
+
+    /// Embeds the query with the current text encoder. Returns `None` if the
+    /// query isn't cached and gets superseded while `ticket` settles.
+    pub async fn embed(&self, query: &str, ticket: &Ticket<'_>) -> Result<Option<Vec<f32>>> {
+        let encoder = self.ai.text_encoder();
+        let model = self.ai.text_model(encoder).to_owned();
+        // Only the CLIP text tower gets translated queries.
+        let provider = match encoder {
+            TextEncoder::Clip => self.translator.provider_name(),
+            TextEncoder::Multilingual => "none",
+        };
+        let query = normalize(query);
+        let key = (model, provider, query);
+        if let Some(embedding) = self.get(&key).await? {
+            return Ok(Some(embedding));
+        }
+
+        if !ticket.settle().await {
+            return Ok(None);
+        }
+        let embedding = embed_text(&self.ai, &self.translator, encoder, key.2.clone()).await?;
+        self.put(key, embedding.clone()).await?;
+        Ok(Some(embedding))
+    }
+
+    /// Deletes persisted embeddings past their TTL, returns how many.
+    pub async fn purge(&self) -> Result<u64> {
+        if !self.persist {
+            return Ok(0);
+        }
+        self.db.purge_query_embeddings(self.ttl_days).await
+    }
+
+    async fn get(&self, key: &Key) -> Result<Option<Vec<f32>>> {
+        if let Some(embedding) = self.cache.lock().unwrap().get(key) {
+            return Ok(Some(embedding.clone()));
+        }
+        if !self.persist {
+            return Ok(None);
+        }
+
+        let (model, provider, query) = key;
+        let embedding = self
+            .db
+            .get_query_embedding(model, provider, query, self.ttl_days)
+            .await?;
+        if let Some(embedding) = &embedding {
+            self.cache
+                .lock()
+                .unwrap()
+                .put(key.clone(), embedding.clone());
+        }
+        Ok(embedding)
+    }
+
+    async fn put(&self, key: Key, embedding: Vec<f32>) -> Result<()> {
+        if self.persist {
+            let (model, provider, query) = key.clone();
+            self.db
+                .save_query_embedding(model, provider, query, embedding.clone())
+                .await?;
+        }
+        self.cache.lock().unwrap().put(key, embedding);
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use entities::reindex_jobs;
+use teloxide::{prelude::*, types::MessageId};
+use tokio::task::JoinSet;
+use tracing::*;
+
+use crate::{ai::Ai, db::Db, download_file, embed_caption, translator::Translator, Bot};
+
+/// Minimal interval between edits of the progress message.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Starts a new job re-embedding every image not embedded with the active
+/// model, reporting its progress to `chat`.
+pub async fn start(
+    bot: Bot,
+    db: Arc<Db>,
+    ai: Arc<Ai>,
+    translator: Arc<Translator>,
+    chat: ChatId,
+) -> Result<()> {
+    if !db.get_unfinished_reindex_jobs().await?.is_empty() {
+        bot.send_message(chat, "Переиндексация уже запущена.")
+            .await?;
+        return Ok(());
+    }
+
+    let msg = bot.send_message(chat, "Переиндексация запущена...").await?;
+    let job = db.create_reindex_job(chat.0, msg.id.0).await?;
+    tokio::spawn(run(bot, db, ai, translator, job));
+    Ok(())
+}
+
+/// Continues jobs interrupted by a restart from their last checkpoint.
+pub async fn resume(bot: Bot, db: Arc<Db>, ai: Arc<Ai>, translator: Arc<Translator>) -> Result<()> {
+    for job in db.get_unfinished_reindex_jobs().await? {
+        info!(
+            "resuming reindex job {} after image {}",
+            job.id, job.last_image_id
+        );
+        tokio::spawn(run(
+            bot.clone(),
+            db.clone(),
+            ai.clone(),
+            translator.clone(),
+            job,
+        ));
+    }
+    Ok(())
+}
+
+async fn run(
+    bot: Bot,
+    db: Arc<Db>,
+    ai: Arc<Ai>,
+    translator: Arc<Translator>,
+    job: reindex_jobs::Model,
+) {
+    let (id, chat, message) = (job.id, ChatId(job.chat_id), MessageId(job.message_id));
+    if let Err(e) = try_run(&bot, &db, &ai, &translator, job).await {
+        error!("reindex job failed: {e:#}");
+        sentry_anyhow::capture_anyhow(&e);
+
+        // A new /reindex picks up the images this job didn't get to.
+        if let Err(e) = db.finish_reindex_job(id).await {
+            error!("can't finish failed reindex job: {e:#}");
+        }
+        bot.edit_message_text(
+            chat,
+            message,
+            format!("Переиндексация прервана: {e:#}. Запустите /reindex, чтобы продолжить."),
+        )
+        .await
+        .map_err(|e| warn!("can't report reindex failure: {e}"))
+        .ok();
+    }
+}
+
+async fn try_run(
+    bot: &Bot,
+    db: &Arc<Db>,
+    ai: &Arc<Ai>,
+    translator: &Arc<Translator>,
+    job: reindex_jobs::Model,
+) -> Result<()> {
+    let chat = ChatId(job.chat_id);
+    let message = MessageId(job.message_id);
+    let mut last_image_id = job.last_image_id;
+    let mut processed = job.processed;
+    let mut failed = job.failed;
+    let mut last_report = Instant::now();
+
+    loop {
+        let images = db
+            .get_stale_images(ai.model(), last_image_id, ai.batch_size() as u64)
+            .await?;
+        let Some(last) = images.last() else {
+            break;
+        };
+        last_image_id = last.id;
+
+        // Download and embed a whole batch concurrently so the batcher can
+        // send it to the model server at once.
+        let mut tasks = JoinSet::new();
+        for image in images {
+            let (bot, db, ai, translator) =
+                (bot.clone(), db.clone(), ai.clone(), translator.clone());
+            tasks.spawn(async move {
+                let res = async {
+                    // Images saved before previews were stored are embedded
+                    // from the file itself.
+                    let preview = image.preview_file_id.as_ref().unwrap_or(&image.file_id);
+                    let dst = download_file(&bot, preview).await?;
+                    let embedding = ai.image_embedding(dst).await?;
+                    let caption_embedding = match &image.caption {
+                        Some(caption) => embed_caption(&db, &ai, &translator, caption).await,
+                        None => None,
+                    };
+                    anyhow::Ok((embedding, caption_embedding))
+                }
+                .await;
+                (image.id, res)
+            });
+        }
+        while let Some(res) = tasks.join_next().await {
+            match res? {
+                (id, Ok((embedding, caption_embedding))) => {
+                    db.update_image(id, embedding, caption_embedding, ai.model())
+                        .await?;
+                    processed += 1;
+                }
+                (id, Err(e)) => {
+                    warn!("failed to reindex {id}: {e:#}");
+                    db.add_reindex_failure(job.id, id, format!("{e:#}")).await?;
+                    failed += 1;
+                }
+            }
+        }
+
+        db.update_reindex_job(job.id, last_image_id, processed, failed)
+            .await?;
+        info!("reindexed up to {last_image_id}");
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            let remaining = db.count_stale_images(ai.model(), last_image_id).await?;
+            bot.edit_message_text(
+                chat,
+                message,
+                format!(
+                    "Переиндексация: обработано {processed}, ошибок {failed}, осталось {remaining}."
+                ),
+            )
+            .await
+            .map_err(|e| warn!("can't update reindex progress: {e}"))
+            .ok();
+        }
+    }
+
+    db.finish_reindex_job(job.id).await?;
+    bot.edit_message_text(
+        chat,
+        message,
+        format!("Переиндексация завершена: обработано {processed}, ошибок {failed}."),
+    )
+    .await?;
+    Ok(())
+}
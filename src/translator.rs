@@ -0,0 +1,442 @@
+use std::{
+    collections::HashMap,
+    env,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use lru::LruCache;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+use whatlang::{Lang, Script};
+
+use crate::db::Db;
+
+/// Translations kept in memory if `TRANSLATION_CACHE_SIZE` is unset.
+const DEFAULT_CACHE_SIZE: usize = 10_000;
+
+#[async_trait]
+pub trait TranslationProvider: Send + Sync {
+    /// Translates `text` from the `source` language (ISO 639-1) to English,
+    /// the provider detects the language itself if `source` is `None`.
+    async fn translate(&self, text: String, source: Option<&str>) -> Result<String>;
+
+    /// Identifier stored with cached translations, so switching providers
+    /// doesn't keep serving the old one's translations.
+    fn name(&self) -> &'static str;
+
+    /// Whether translations should be cached, `false` for providers that
+    /// don't call any service.
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+pub struct YandexProvider {
+    api_key: String,
+    folder: String,
+    client: Client,
+}
+
+impl YandexProvider {
+    pub fn new(api_key: String, folder: String) -> Self {
+        Self {
+            api_key,
+            folder,
+            client: Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl TranslationProvider for YandexProvider {
+    fn name(&self) -> &'static str {
+        "yandex"
+    }
+
+    async fn translate(&self, text: String, source: Option<&str>) -> Result<String> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct TranslateRequest {
+            folder_id: String,
+            texts: [String; 1],
+            target_language_code: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            source_language_code: Option<String>,
+            speller: bool,
+        }
+
+        #[derive(Deserialize)]
+        struct TranslateResponse {
+            translations: [Translation; 1],
+        }
+
+        #[derive(Deserialize)]
+        struct Translation {
+            text: String,
+        }
+
+        let res: TranslateResponse = self
+            .client
+            .post("https://translate.api.cloud.yandex.net/translate/v2/translate")
+            .header("Authorization", format!("Api-Key {}", self.api_key))
+            .json(&TranslateRequest {
+                folder_id: self.folder.clone(),
+                texts: [text],
+                target_language_code: "en".into(),
+                source_language_code: source.map(Into::into),
+                speller: true,
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(res.translations.into_iter().map(|t| t.text).next().unwrap())
+    }
+}
+
+/// Client for LibreTranslate and compatible `/translate` APIs.
+pub struct LibreTranslateProvider {
+    url: String,
+    api_key: Option<String>,
+    client: Client,
+}
+
+impl LibreTranslateProvider {
+    pub fn new(url: String, api_key: Option<String>) -> Self {
+        Self {
+            url,
+            api_key,
+            client: Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl TranslationProvider for LibreTranslateProvider {
+    fn name(&self) -> &'static str {
+        "libretranslate"
+    }
+
+    async fn translate(&self, text: String, source: Option<&str>) -> Result<String> {
+        #[derive(Serialize)]
+        struct TranslateRequest<'a> {
+            q: String,
+            source: &'a str,
+            target: &'a str,
+            format: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            api_key: Option<&'a str>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct TranslateResponse {
+            translated_text: String,
+        }
+
+        let res: TranslateResponse = self
+            .client
+            .post(format!("{}/translate", self.url))
+            .json(&TranslateRequest {
+                q: text,
+                source: source.unwrap_or("auto"),
+                target: "en",
+                format: "text",
+                api_key: self.api_key.as_deref(),
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(res.translated_text)
+    }
+}
+
+/// Leaves texts as they are, for running without a translation service.
+pub struct PassthroughProvider;
+
+#[async_trait]
+impl TranslationProvider for PassthroughProvider {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    async fn translate(&self, text: String, _: Option<&str>) -> Result<String> {
+        Ok(text)
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// Language of a text to translate.
+#[derive(Debug, PartialEq)]
+enum Language {
+    /// English or no words at all, e.g. only emoji, nothing to translate.
+    English,
+    /// ISO 639-1 code of a detected language.
+    Detected(&'static str),
+    /// Left for the provider to detect.
+    Unknown,
+}
+
+impl Language {
+    fn detect(text: &str) -> Self {
+        if !text.chars().any(char::is_alphabetic) {
+            return Self::English;
+        }
+        let Some(info) = whatlang::detect(text) else {
+            return Self::Unknown;
+        };
+
+        match info.script() {
+            // Short queries in Latin script are most likely English.
+            Script::Latin if info.lang() == Lang::Eng || !info.is_reliable() => Self::English,
+            // Same for Russian in Cyrillic.
+            Script::Cyrillic if !info.is_reliable() => Self::Detected("ru"),
+            _ => iso_639_1(info.lang()).map_or(Self::Unknown, Self::Detected),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::Detected(code) => code,
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+fn iso_639_1(lang: Lang) -> Option<&'static str> {
+    Some(match lang {
+        Lang::Ara => "ar",
+        Lang::Aze => "az",
+        Lang::Bel => "be",
+        Lang::Bul => "bg",
+        Lang::Ces => "cs",
+        Lang::Cmn => "zh",
+        Lang::Dan => "da",
+        Lang::Deu => "de",
+        Lang::Ell => "el",
+        Lang::Eng => "en",
+        Lang::Est => "et",
+        Lang::Fin => "fi",
+        Lang::Fra => "fr",
+        Lang::Heb => "he",
+        Lang::Hin => "hi",
+        Lang::Hrv => "hr",
+        Lang::Hun => "hu",
+        Lang::Ind => "id",
+        Lang::Ita => "it",
+        Lang::Jpn => "ja",
+        Lang::Kat => "ka",
+        Lang::Kor => "ko",
+        Lang::Lav => "lv",
+        Lang::Lit => "lt",
+        Lang::Mkd => "mk",
+        Lang::Nld => "nl",
+        Lang::Nob => "no",
+        Lang::Pes => "fa",
+        Lang::Pol => "pl",
+        Lang::Por => "pt",
+        Lang::Ron => "ro",
+        Lang::Rus => "ru",
+        Lang::Slv => "sl",
+        Lang::Spa => "es",
+        Lang::Srp => "sr",
+        Lang::Swe => "sv",
+        Lang::Tha => "th",
+        Lang::Tur => "tr",
+        Lang::Ukr => "uk",
+        Lang::Uzb => "uz",
+        Lang::Vie => "vi",
+        _ => return None,
+    })
+}
+
+/// Translates queries and captions to English, caching translations in
+/// memory and in the database so each text is translated only once.
+pub struct Translator {
+    provider: Box<dyn TranslationProvider>,
+    db: Arc<Db>,
+    cache: Mutex<LruCache<String, String>>,
+    /// Number of translated texts per detected language.
+    language_stats: Mutex<HashMap<&'static str, u64>>,
+}
+
+impl Translator {
+    pub fn new(provider: Box<dyn TranslationProvider>, db: Arc<Db>) -> Result<Self> {
+        let cache_size = match env::var("TRANSLATION_CACHE_SIZE") {
+            Ok(size) => size.parse().context("invalid TRANSLATION_CACHE_SIZE")?,
+            Err(_) => DEFAULT_CACHE_SIZE,
+        };
+
+        Ok(Self {
+            provider,
+            db,
+            cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(cache_size).context("TRANSLATION_CACHE_SIZE must be positive")?,
+            )),
+            language_stats: Mutex::default(),
+        })
+    }
+
+    /// Picks the provider from `TRANSLATION_PROVIDER` (`yandex`,
+    /// `libretranslate` or `none`). Defaults to Yandex if `YCL_API_KEY` is
+    /// set and to no translation otherwise.
+    pub fn from_env(db: Arc<Db>) -> Result<Self> {
+        let provider_name = match env::var("TRANSLATION_PROVIDER") {
+            Ok(name) => name,
+            Err(_) if env::var("YCL_API_KEY").is_ok() => "yandex".to_owned(),
+            Err(_) => {
+                warn!("YCL_API_KEY isn't set, queries won't be translated");
+                "none".to_owned()
+            }
+        };
+
+        let provider: Box<dyn TranslationProvider> = match provider_name.as_str() {
+            "yandex" => Box::new(YandexProvider::new(
+                env::var("YCL_API_KEY")?,
+                env::var("YCL_FOLDER")?,
+            )),
+            "libretranslate" => Box::new(LibreTranslateProvider::new(
+                env::var("LIBRETRANSLATE_URL").context("LIBRETRANSLATE_URL isn't set")?,
+                env::var("LIBRETRANSLATE_API_KEY").ok(),
+            )),
+            "none" => Box::new(PassthroughProvider),
+            other => bail!("unknown TRANSLATION_PROVIDER: {other}"),
+        };
+        Self::new(provider, db)
+    }
+
+    pub fn provider_name(&self) -> &'static str {
+        self.provider.name()
+    }
+
+    /// Returns the number of translated texts per language, most frequent
+    /// first.
+    pub fn language_stats(&self) -> Vec<(&'static str, u64)> {
+        let mut stats: Vec<_> = self
+            .language_stats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(lang, count)| (*lang, *count))
+            .collect();
+        stats.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        stats
+    }
+
+    pub async fn translate(&self, text: String) -> Result<String> {
+        let language = Language::detect(&text);
+        *self
+            .language_stats
+            .lock()
+            .unwrap()
+            .entry(language.code())
+            .or_default() += 1;
+
+        let source = match language {
+            Language::English => return Ok(text),
+            Language::Detected(code) => Some(code),
+            Language::Unknown => None,
+        };
+
+        if !self.provider.cacheable() {
+            return self.provider.translate(text, source).await;
+        }
+
+        let cached_translation = self.cache.lock().unwrap().get(&text).cloned();
+        if let Some(translation) = cached_translation {
+            return Ok(translation);
+        }
+
+        // The detected language is part of the key as it changes the
+        // request to the provider.
+        let (provider, source_language) = (self.provider.name(), language.code());
+        let translation = match self
+            .db
+            .get_translation(provider, source_language, text.clone())
+            .await?
+        {
+            Some(translation) => translation,
+            None => {
+                let translation = self.provider.translate(text.clone(), source).await?;
+                self.db
+                    .save_translation(provider, source_language, text.clone(), translation.clone())
+                    .await?;
+                translation
+            }
+        };
+
+        self.cache.lock().unwrap().put(text, translation.clone());
+        Ok(translation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn english_is_not_translated() {
+        assert_eq!(Language::detect("sleeping cat"), Language::English);
+        assert_eq!(
+            Language::detect("a cat sleeping on the keyboard in the morning"),
+            Language::English
+        );
+    }
+
+    #[test]
+    fn text_without_letters_is_not_translated() {
+        assert_eq!(Language::detect(""), Language::English);
+        assert_eq!(Language::detect("🐱🔥 42"), Language::English);
+    }
+
+    // Short Latin-script queries are taken for English even when they are
+    // not: whatlang can't tell languages apart from a word or two, and
+    // translating English as some other language garbles far more queries.
+    #[test]
+    fn short_latin_queries_are_taken_for_english() {
+        assert_eq!(Language::detect("Katze"), Language::English);
+        assert_eq!(Language::detect("gato"), Language::English);
+    }
+
+    #[test]
+    fn long_latin_queries_are_detected() {
+        assert_eq!(
+            Language::detect("eine schwarze Katze schläft auf dem Sofa neben dem Fenster"),
+            Language::Detected("de")
+        );
+    }
+
+    #[test]
+    fn short_cyrillic_queries_are_taken_for_russian() {
+        assert_eq!(Language::detect("кот"), Language::Detected("ru"));
+        assert_eq!(Language::detect("спящий кот"), Language::Detected("ru"));
+    }
+
+    #[test]
+    fn long_cyrillic_queries_are_detected() {
+        assert_eq!(
+            Language::detect("чорний кіт спить на дивані біля вікна і їсть їжу"),
+            Language::Detected("uk")
+        );
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(Language::English.code(), "en");
+        assert_eq!(Language::Detected("ru").code(), "ru");
+        assert_eq!(Language::Unknown.code(), "unknown");
+    }
+}